@@ -1,5 +1,7 @@
 #!/bin/sh
 
+# the running server, or "--direct" to open the port by this command
+server="http://localhost:8080"
 port_name="/dev/ttyUSB0"
 
-cargo run --release --bin command -- "$@" -p $port_name
+cargo run --release --bin command -- "$@" --server $server -p $port_name
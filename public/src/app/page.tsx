@@ -4,30 +4,13 @@ import React, { useState, useEffect } from "react";
 
 import { useMHV4Data } from "@/contexts/MHV4Context";
 import ShowDate from "@/components/show-date";
+import LinkStatus from "@/components/link-status";
 import RCButton from "@/components/rc-button";
 import PrintButton from "@/components/print-button";
 import MHV4Table from "@/components/mhv4-table";
 import OnoffButton from "@/components/onoff-button";
 import ApplyButton from "@/components/apply-button";
-
-const mhv4_descriptions = [
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-  ["---", "---"],
-];
+import RampButton from "@/components/ramp-button";
 
 export default function Home() {
   const { voltageArray, isOnArray } = useMHV4Data();
@@ -60,15 +43,16 @@ export default function Home() {
     <main>
       <h1 className="bg-gray-100 px-5 py-5 text-3xl font-bold">MHV4 monitor</h1>
       <ShowDate />
+      <LinkStatus />
       <PrintButton />
       <RCButton />
       <MHV4Table
-        userDescription={mhv4_descriptions}
         onCheckedChange={(state, index) => handleStateChange(state, index)}
         onValueChange={(newValue, index) => handleValueChange(newValue, index)}
       />
       <OnoffButton inputs={onoffStates} />
       <ApplyButton inputs={inputValues} />
+      <RampButton />
     </main>
   );
 }
@@ -2,6 +2,8 @@
 
 import React, { useState } from "react";
 
+import { authFetch } from "@/lib/auth";
+
 interface InputProps {
   inputs: number[];
 }
@@ -14,15 +16,18 @@ const ApplyButton: React.FC<InputProps> = ({ inputs }) => {
     console.log("input value:", inputs);
     const send_data: number[] = inputs.map((number) => number * 10);
     try {
-      const response = await fetch(`${process.env.NEXT_PUBLIC_HV_ROUTE}`, {
+      const response = await authFetch(`${process.env.NEXT_PUBLIC_HV_ROUTE}`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
         },
         body: JSON.stringify(send_data),
       });
+      // {"code": ..., "message": ...}, nothing is changed by the server
       if (!response.ok) {
-        throw new Error(`Error: ${response.status}`);
+        const error = await response.json();
+        alert(`Rejected: ${error.message}`);
+        return;
       }
       const responseData = await response.json();
       console.log("Result from apply route:", responseData);
@@ -0,0 +1,46 @@
+"use client";
+
+import React from "react";
+
+import { useMHV4Data } from "@/contexts/MHV4Context";
+
+// nothing is shown while the serial links are up and the modules are scanned
+const LinkStatus: React.FC = () => {
+  const { links, unavailable, down } = useMHV4Data();
+
+  return (
+    <>
+      {Object.entries(links)
+        .filter(([, link]) => link.state !== "connected")
+        .map(([controller, link]) => {
+          let message = `Serial link of ${controller} is lost`;
+          if (link.state === "reconnecting") {
+            message += `, reconnecting in ${link.retry_in_ms} ms (attempt ${link.attempt})`;
+          }
+          return (
+            <div
+              key={controller}
+              className="block bg-red-700 p-2 text-primary-foreground"
+            >
+              {message}: {link.error}
+            </div>
+          );
+        })}
+      {Object.entries(down).map(([controller, reason]) => (
+        <div
+          key={`down-${controller}`}
+          className="block bg-yellow-600 p-2 text-primary-foreground"
+        >
+          {controller} could not be scanned, retrying: {reason}
+        </div>
+      ))}
+      {unavailable && (
+        <div className="block bg-yellow-600 p-2 text-primary-foreground">
+          Hardware is unavailable, retrying: {unavailable}
+        </div>
+      )}
+    </>
+  );
+};
+
+export default LinkStatus;
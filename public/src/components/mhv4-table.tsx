@@ -45,18 +45,17 @@ const processCurrentArray = (inputArray: number[]): string[] =>
   processNumberArray(inputArray, (value) => value * 0.001, 3);
 
 interface InputProps {
-  userDescription: string[][];
   onCheckedChange: (state: boolean, index: number) => void;
   onValueChange: (newValue: number, index: number) => void;
 }
 
 const MHV4Table: React.FC<InputProps> = ({
-  userDescription,
   onCheckedChange,
   onValueChange,
 }) => {
   const {
     progressType,
+    controllerArray,
     busArray,
     devArray,
     chArray,
@@ -64,6 +63,9 @@ const MHV4Table: React.FC<InputProps> = ({
     currentArray,
     isOnArray,
     isPositiveArray,
+    isTrippedArray,
+    nameArray,
+    descriptionArray,
   } = useMHV4Data();
   const onoffs = processOnOffArray(isOnArray);
   const pols = processPolArray(isPositiveArray);
@@ -85,6 +87,7 @@ const MHV4Table: React.FC<InputProps> = ({
     <Table className={border_style}>
       <TableHeader className="bg-blue-100">
         <TableRow>
+          <TableHead className="font-bold">controller</TableHead>
           <TableHead className="font-bold">bus</TableHead>
           <TableHead className="font-bold">dev</TableHead>
           <TableHead className="font-bold">ch</TableHead>
@@ -100,7 +103,10 @@ const MHV4Table: React.FC<InputProps> = ({
       </TableHeader>
       <TableBody>
         {busArray.map((bus, index) => (
-          <TableRow key={bus}>
+          <TableRow
+            key={`${controllerArray[index]}:${bus}:${devArray[index]}:${chArray[index]}`}
+          >
+            <TableCell className="border">{controllerArray[index]}</TableCell>
             <TableCell className="border">{bus}</TableCell>
             <TableCell className="border">{devArray[index]}</TableCell>
             <TableCell className="border">{chArray[index]}</TableCell>
@@ -130,12 +136,18 @@ const MHV4Table: React.FC<InputProps> = ({
               />
             </TableCell>
             <TableCell className="border">{voltages[index]}</TableCell>
-            <TableCell className="border">{currents[index]}</TableCell>
+            <TableCell
+              className={
+                isTrippedArray[index] ? "border bg-red-200 font-bold" : "border"
+              }
+            >
+              {currents[index]}
+            </TableCell>
             <TableCell className="border">
-              {userDescription[index][0]}
+              {nameArray[index] ?? "---"}
             </TableCell>
             <TableCell className="border">
-              {userDescription[index][1]}
+              {descriptionArray[index] ?? "---"}
             </TableCell>
           </TableRow>
         ))}
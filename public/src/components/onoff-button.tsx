@@ -3,6 +3,7 @@
 import React, { useState } from "react";
 
 import { useMHV4Data } from "@/contexts/MHV4Context";
+import { authFetch } from "@/lib/auth";
 
 interface StateProps {
   inputs: boolean[];
@@ -16,15 +17,18 @@ const OnoffButton: React.FC<StateProps> = ({ inputs }) => {
     setLoading(true);
     console.log("input value:", inputs);
     try {
-      const response = await fetch(`${process.env.NEXT_PUBLIC_ONOFF_ROUTE}`, {
+      const response = await authFetch(`${process.env.NEXT_PUBLIC_ONOFF_ROUTE}`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
         },
         body: JSON.stringify(inputs),
       });
+      // {"code": ..., "message": ...}, nothing is changed by the server
       if (!response.ok) {
-        throw new Error(`Error: ${response.status}`);
+        const error = await response.json();
+        alert(`Rejected: ${error.message}`);
+        return;
       }
       const responseData = await response.json();
       console.log("Result from onoff route:", responseData);
@@ -0,0 +1,79 @@
+"use client";
+
+import React, { useState } from "react";
+
+import { useMHV4Data } from "@/contexts/MHV4Context";
+import { authFetch } from "@/lib/auth";
+
+const RampButton: React.FC = () => {
+  const { progressType } = useMHV4Data();
+  const [loading, setLoading] = useState(false);
+
+  const handleSubmit = async (operation: string) => {
+    setLoading(true);
+    console.log("ramp operation:", operation);
+    try {
+      const response = await authFetch(
+        `${process.env.NEXT_PUBLIC_RAMP_ROUTE}/${operation}`,
+        {
+          method: "POST",
+        },
+      );
+      // {"code": ..., "message": ...}, nothing is changed by the server
+      if (!response.ok) {
+        const error = await response.json();
+        alert(`Rejected: ${error.message}`);
+        return;
+      }
+      const responseData = await response.json();
+      console.log("Result from ramp route:", responseData);
+    } catch (error) {
+      console.error("Fetch error:", error);
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  let style_str = "inline-flex items-center justify-center";
+  style_str += " whitespace-nowrap rounded-md text-sm font-medium";
+  style_str += " ring-offset-background transition-colors";
+  style_str += " focus-visible:outline-none focus-visible:ring-2";
+  style_str += " focus-visible:ring-ring focus-visible:ring-offset-2";
+  style_str += " disabled:pointer-events-none disabled:opacity-50";
+  style_str += " mx-1 h-8 px-4 py-2";
+
+  const yellow_str =
+    style_str + " bg-yellow-600 text-primary-foreground hover:bg-yellow-600/80";
+  const red_str =
+    style_str + " bg-red-700 text-primary-foreground hover:bg-red-700/80";
+  return (
+    <div className="inline-flex">
+      <div className={yellow_str}>
+        <button
+          onClick={() => handleSubmit("pause")}
+          disabled={loading || !progressType}
+        >
+          pause ramp
+        </button>
+      </div>
+      <div className={yellow_str}>
+        <button
+          onClick={() => handleSubmit("resume")}
+          disabled={loading || !progressType}
+        >
+          resume ramp
+        </button>
+      </div>
+      <div className={red_str}>
+        <button
+          onClick={() => handleSubmit("abort")}
+          disabled={loading || !progressType}
+        >
+          abort ramp
+        </button>
+      </div>
+    </div>
+  );
+};
+
+export default RampButton;
@@ -1,6 +1,7 @@
 "use client";
 
 import React, { useState } from "react";
+
 import {
   AlertDialog,
   AlertDialogAction,
@@ -13,6 +14,7 @@ import {
   AlertDialogTrigger,
 } from "@/components/ui/alert-dialog";
 import { useMHV4Data } from "@/contexts/MHV4Context";
+import { authFetch } from "@/lib/auth";
 
 const RCButton: React.FC = () => {
   const { rcType, setRCType } = useMHV4Data();
@@ -22,15 +24,18 @@ const RCButton: React.FC = () => {
     setLoading(true);
     console.log("input value: RC mode is >", rcType);
     try {
-      const response = await fetch(`${process.env.NEXT_PUBLIC_STATUS_ROUTE}`, {
+      const response = await authFetch(`${process.env.NEXT_PUBLIC_STATUS_ROUTE}`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
         },
         body: JSON.stringify(!rcType),
       });
+      // {"code": ..., "message": ...}, nothing is changed by the server
       if (!response.ok) {
-        throw new Error(`Error: ${response.status}`);
+        const error = await response.json();
+        alert(`Rejected: ${error.message}`);
+        return;
       }
       const responseData = await response.json();
       console.log("Result from apply route:", responseData);
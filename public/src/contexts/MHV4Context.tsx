@@ -5,27 +5,47 @@ import React, {
   useContext,
   useState,
   useEffect,
+  useRef,
   ReactNode,
 } from "react";
 
 import {
   getInitRCStatus,
   getInitProgStatus,
+  getInitMHV4controller,
   getInitMHV4bus,
   getInitMHV4dev,
   getInitMHV4ch,
   getInitMHV4onoff,
   getInitMHV4pol,
+  getInitLinks,
+  getInitUnavailable,
+  getInitDown,
 } from "@/lib/transformInitData";
 
 import {
   getSSEProgStatus,
   getSSEVoltageArray,
   getSSECurrentArray,
+  isSSETripped,
+  TripEventType,
+  StateEventType,
+  ChannelsEventType,
+  LinksType,
+  DownType,
+  channelKey,
 } from "@/lib/transformSSEData";
 
+import {
+  getConfigName,
+  getConfigDescription,
+} from "@/lib/transformConfigData";
+
+import { authFetch, withToken } from "@/lib/auth";
+
 type RCType = boolean;
 type ProgressType = boolean;
+type ControllerType = string[];
 type BusType = number[];
 type DevType = number[];
 type ChType = number[];
@@ -33,11 +53,18 @@ type VoltageType = number[];
 type CurrentType = number[];
 type IsOnType = boolean[];
 type IsPositiveType = boolean[];
+type IsTrippedType = boolean[];
+type NameType = string[];
+type DescriptionType = string[];
 
 interface MHV4ContextType {
   rcType: RCType;
   setRCType: (newValue: RCType) => void;
   progressType: ProgressType;
+  links: LinksType;
+  unavailable: string | null;
+  down: DownType;
+  controllerArray: ControllerType;
   busArray: BusType;
   devArray: DevType;
   chArray: ChType;
@@ -46,12 +73,19 @@ interface MHV4ContextType {
   isOnArray: IsOnType;
   setIsOnArray: (newStates: IsOnType) => void;
   isPositiveArray: IsPositiveType;
+  isTrippedArray: IsTrippedType;
+  nameArray: NameType;
+  descriptionArray: DescriptionType;
 }
 
 const defaultState: MHV4ContextType = {
   rcType: false,
   setRCType: () => {},
   progressType: false,
+  links: {},
+  unavailable: null,
+  down: {},
+  controllerArray: [],
   busArray: [],
   devArray: [],
   chArray: [],
@@ -60,6 +94,9 @@ const defaultState: MHV4ContextType = {
   isOnArray: [],
   setIsOnArray: () => {},
   isPositiveArray: [],
+  isTrippedArray: [],
+  nameArray: [],
+  descriptionArray: [],
 };
 
 const MHV4Context = createContext<MHV4ContextType>(defaultState);
@@ -73,6 +110,14 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
   const [progressType, setProgressType] = useState<ProgressType>(
     defaultState.progressType,
   );
+  const [links, setLinks] = useState<LinksType>(defaultState.links);
+  const [unavailable, setUnavailable] = useState<string | null>(
+    defaultState.unavailable,
+  );
+  const [down, setDown] = useState<DownType>(defaultState.down);
+  const [controllerArray, setControllerArray] = useState<ControllerType>(
+    defaultState.controllerArray,
+  );
   const [busArray, setBusArray] = useState<BusType>(defaultState.busArray);
   const [devArray, setDevArray] = useState<DevType>(defaultState.devArray);
   const [chArray, setChArray] = useState<ChType>(defaultState.chArray);
@@ -88,11 +133,21 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
   const [isPositiveArray, setIsPositiveArray] = useState<IsPositiveType>(
     defaultState.isPositiveArray,
   );
+  const [isTrippedArray, setIsTrippedArray] = useState<IsTrippedType>(
+    defaultState.isTrippedArray,
+  );
+  const [nameArray, setNameArray] = useState<NameType>(defaultState.nameArray);
+  const [descriptionArray, setDescriptionArray] = useState<DescriptionType>(
+    defaultState.descriptionArray,
+  );
+
+  // the readings are matched to the channels of the last "/mhv4_data"
+  const channelKeys = useRef<string[]>([]);
 
   useEffect(() => {
     const fetchData = async () => {
       try {
-        const response = await fetch(`${process.env.NEXT_PUBLIC_INIT_ROUTE}`);
+        const response = await authFetch(`${process.env.NEXT_PUBLIC_INIT_ROUTE}`);
         if (!response.ok) {
           throw new Error("failed to fetch the MHV4 data");
         }
@@ -102,9 +157,20 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
         // set initial state
         setRCType(getInitRCStatus(data));
         setProgressType(getInitProgStatus(data));
-        setBusArray(getInitMHV4bus(data));
-        setDevArray(getInitMHV4dev(data));
-        setChArray(getInitMHV4ch(data));
+        setLinks(getInitLinks(data));
+        setUnavailable(getInitUnavailable(data));
+        setDown(getInitDown(data));
+        const controllers = getInitMHV4controller(data);
+        const buses = getInitMHV4bus(data);
+        const devs = getInitMHV4dev(data);
+        const chs = getInitMHV4ch(data);
+        channelKeys.current = controllers.map((controller, i) =>
+          channelKey({ controller, bus: buses[i], dev: devs[i], ch: chs[i] }),
+        );
+        setControllerArray(controllers);
+        setBusArray(buses);
+        setDevArray(devs);
+        setChArray(chs);
         setIsOnArray(getInitMHV4onoff(data));
         setIsPositiveArray(getInitMHV4pol(data));
       } catch (error) {
@@ -112,9 +178,27 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
       }
     };
 
+    // detector names are given by the server config
+    const fetchConfig = async () => {
+      try {
+        const response = await authFetch(`${process.env.NEXT_PUBLIC_CONFIG_ROUTE}`);
+        if (!response.ok) {
+          throw new Error("failed to fetch the channel config");
+        }
+        const config = await response.json();
+        setNameArray(getConfigName(config));
+        setDescriptionArray(getConfigDescription(config));
+      } catch (error) {
+        console.error("Failed to fetch channel config:", error);
+      }
+    };
+
     fetchData();
+    fetchConfig();
 
-    const eventSource = new EventSource(`${process.env.NEXT_PUBLIC_SSE_ROUTE}`);
+    const eventSource = new EventSource(
+      withToken(`${process.env.NEXT_PUBLIC_SSE_ROUTE}`),
+    );
     eventSource.onopen = (event) => {
       console.log("SSE connection opened: ", event);
     };
@@ -123,9 +207,37 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
       const ssedata = JSON.parse(event.data);
       // set SSE data
       setProgressType(getSSEProgStatus(ssedata));
-      setVolArray(getSSEVoltageArray(ssedata));
-      setCurArray(getSSECurrentArray(ssedata));
+      setVolArray(getSSEVoltageArray(ssedata, channelKeys.current));
+      setCurArray(getSSECurrentArray(ssedata, channelKeys.current));
     };
+    eventSource.addEventListener("trip", (event) => {
+      console.warn("trip event received: ", event);
+      const tripEvent: TripEventType = JSON.parse(event.data);
+      setIsTrippedArray((currentArray) => {
+        const newArray = [...currentArray];
+        newArray[tripEvent.index] = isSSETripped(tripEvent);
+        return newArray;
+      });
+    });
+    // keeps the consoles consistent
+    eventSource.addEventListener("state", (event) => {
+      console.log("state event received: ", event);
+      const stateEvent: StateEventType = JSON.parse(event.data);
+      setRCType(stateEvent.is_rc);
+      setProgressType(stateEvent.is_progress);
+      setLinks(stateEvent.links);
+      setUnavailable(stateEvent.unavailable);
+      setDown(stateEvent.down);
+      setIsOnArray(stateEvent.is_on);
+    });
+    // modules are added or removed by a rescan
+    eventSource.addEventListener("channels", (event) => {
+      console.warn("channels event received: ", event);
+      const channelsEvent: ChannelsEventType = JSON.parse(event.data);
+      setIsTrippedArray(channelsEvent.channels.map(() => false));
+      fetchData();
+      fetchConfig();
+    });
     eventSource.onerror = (event) => {
       console.error("SSE connection error: ", event);
       setVolArray((currentArray) => {
@@ -149,6 +261,10 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
         rcType,
         setRCType,
         progressType,
+        links,
+        unavailable,
+        down,
+        controllerArray,
         busArray,
         devArray,
         chArray,
@@ -157,6 +273,9 @@ export const MHV4DataProvider: React.FC<MHV4ProviderProps> = ({ children }) => {
         isOnArray,
         setIsOnArray,
         isPositiveArray,
+        isTrippedArray,
+        nameArray,
+        descriptionArray,
       }}
     >
       {children}
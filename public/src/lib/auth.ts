@@ -0,0 +1,48 @@
+// the token is given once by "?token=..." of the page URL or by the prompt,
+// and kept in the local storage of the browser
+const TOKEN_KEY = "mhv4_token";
+
+const getToken = (): string | null => {
+  if (typeof window === "undefined") {
+    return null;
+  }
+  const fromUrl = new URLSearchParams(window.location.search).get("token");
+  if (fromUrl) {
+    localStorage.setItem(TOKEN_KEY, fromUrl);
+  }
+  return localStorage.getItem(TOKEN_KEY);
+};
+
+// EventSource cannot send the header
+export const withToken = (url: string): string => {
+  const token = getToken();
+  if (!token) {
+    return url;
+  }
+  const separator = url.includes("?") ? "&" : "?";
+  return `${url}${separator}token=${encodeURIComponent(token)}`;
+};
+
+const send = (url: string, init: RequestInit, token: string | null) => {
+  const headers = new Headers(init.headers);
+  if (token) {
+    headers.set("Authorization", `Bearer ${token}`);
+  }
+  return fetch(url, { ...init, headers });
+};
+
+// fetch with the token, asks for a new one when the server rejects it
+export const authFetch = async (
+  url: string,
+  init: RequestInit = {},
+): Promise<Response> => {
+  const response = await send(url, init, getToken());
+  if (response.status === 401) {
+    const token = window.prompt("token of mhv4_monitor");
+    if (token) {
+      localStorage.setItem(TOKEN_KEY, token);
+      return send(url, init, token);
+    }
+  }
+  return response;
+};
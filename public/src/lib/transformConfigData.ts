@@ -0,0 +1,30 @@
+interface ChannelConfig {
+  controller: string;
+  bus: number;
+  dev: number;
+  ch: number;
+  name: string;
+  group: string | null;
+  polarity: "positive" | "negative" | null;
+  operating_voltage: number | null;
+  max_voltage: number | null;
+  current_limit: number | null;
+  notes: string | null;
+}
+
+// in the order of the MHV4 data, null when the channel is not configured
+type ConfigResponse = (ChannelConfig | null)[];
+
+export const getConfigName = (configResponse: ConfigResponse): string[] =>
+  configResponse.map((channel) => (channel ? channel.name : "---"));
+
+export const getConfigDescription = (
+  configResponse: ConfigResponse,
+): string[] =>
+  configResponse.map((channel) => {
+    if (!channel) {
+      return "---";
+    }
+    const group = channel.group ? `[${channel.group}] ` : "";
+    return group + (channel.notes ?? "");
+  });
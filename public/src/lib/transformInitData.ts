@@ -1,4 +1,7 @@
+import { DownType, LinksType } from "@/lib/transformSSEData";
+
 interface MHV4Data {
+  controller: string;
   idc: number;
   bus: number;
   dev: number;
@@ -12,19 +15,29 @@ interface MHV4Response {
   mhv4_data_array: MHV4Data[];
   is_rc: boolean;
   is_progress: boolean;
+  links: LinksType;
+  unavailable: string | null;
+  down: DownType;
 }
 
 export const getInitRCStatus = (mhv4Response: MHV4Response): boolean =>
   mhv4Response.is_rc;
 export const getInitProgStatus = (mhv4Response: MHV4Response): boolean =>
   mhv4Response.is_progress;
+export const getInitLinks = (mhv4Response: MHV4Response): LinksType =>
+  mhv4Response.links;
+export const getInitUnavailable = (
+  mhv4Response: MHV4Response,
+): string | null => mhv4Response.unavailable;
+export const getInitDown = (mhv4Response: MHV4Response): DownType =>
+  mhv4Response.down;
 
 function getInitMHV4Data(
   mhv4Response: MHV4Response,
   key: keyof MHV4Data,
-): (number | boolean)[] {
+): (string | number | boolean)[] {
   return mhv4Response.mhv4_data_array.reduce(
-    (acc: (number | boolean)[], mod) => {
+    (acc: (string | number | boolean)[], mod) => {
       acc.push(mod[key]);
       return acc;
     },
@@ -32,6 +45,10 @@ function getInitMHV4Data(
   );
 }
 
+export const getInitMHV4controller = (
+  mhv4Response: MHV4Response,
+): string[] => getInitMHV4Data(mhv4Response, "controller") as string[];
+
 export const getInitMHV4bus = (mhv4Response: MHV4Response): number[] =>
   getInitMHV4Data(mhv4Response, "bus") as number[];
 
@@ -1,10 +1,90 @@
-type SSEType = [voltage: number[], current: number[], is_progress: boolean];
+// the voltage in 0.1 V and the current in nA of one channel
+type ChannelReadingType = ChannelIdType & {
+  voltage: number;
+  current: number;
+};
+
+type SSEType = { channels: ChannelReadingType[]; is_progress: boolean };
+
+// sent when the value could not be read
+const READ_ERROR_VALUE = -100000;
+
+// "target:0:1:2" like the server
+export const channelKey = (id: ChannelIdType): string =>
+  `${id.controller}:${id.bus}:${id.dev}:${id.ch}`;
 
 export const getSSEProgStatus = (sseResponse: SSEType): boolean =>
-  sseResponse[2];
+  sseResponse.is_progress;
+
+// the readings in the order of "keys", a channel which is not in the reading is a read error
+const getSSEValues = (
+  sseResponse: SSEType,
+  keys: string[],
+  value: "voltage" | "current",
+): number[] => {
+  const readings = new Map(
+    sseResponse.channels.map((x) => [channelKey(x), x[value]]),
+  );
+  return keys.map((key) => readings.get(key) ?? READ_ERROR_VALUE);
+};
+
+export const getSSEVoltageArray = (
+  sseResponse: SSEType,
+  keys: string[],
+): number[] => getSSEValues(sseResponse, keys, "voltage");
+
+export const getSSECurrentArray = (
+  sseResponse: SSEType,
+  keys: string[],
+): number[] => getSSEValues(sseResponse, keys, "current");
+
+export type TripEventType = {
+  kind: "tripped" | "released";
+  index: number;
+  current: number;
+  threshold: number;
+  action: string;
+  time_ms: number;
+};
+
+export const isSSETripped = (tripEvent: TripEventType): boolean =>
+  tripEvent.kind === "tripped";
+
+// "reconnecting" has the attempt, the pause before it and the last error
+export type LinkStateType = {
+  state: "connected" | "disconnected" | "reconnecting";
+  attempt?: number;
+  retry_in_ms?: number;
+  error?: string;
+};
+
+// the serial link of every controller by its name
+export type LinksType = Record<string, LinkStateType>;
+
+// the controllers which could not be scanned, with the reason
+export type DownType = Record<string, string>;
+
+// sent when the RC mode, the hardware, ON/OFF or setpoints are changed
+export type StateEventType = {
+  is_rc: boolean;
+  is_progress: boolean;
+  links: LinksType;
+  unavailable: string | null;
+  down: DownType;
+  is_on: boolean[];
+  setpoints: number[];
+};
 
-export const getSSEVoltageArray = (sseResponse: SSEType): number[] => 
-  sseResponse[0];
+export type ChannelIdType = {
+  controller: string;
+  bus: number;
+  dev: number;
+  ch: number;
+};
 
-export const getSSECurrentArray = (sseResponse: SSEType): number[] =>
-  sseResponse[1];
+// sent when a rescan adds or removes channels
+export type ChannelsEventType = {
+  channels: ChannelIdType[];
+  added: ChannelIdType[];
+  removed: ChannelIdType[];
+};
@@ -5,17 +5,44 @@ log_type="trace" # info, debug, trace
 
 # for read error
 max_voltage="3000"
-port_name="/dev/ttyUSB0"
+port_name="/dev/ttyUSB0" # serial:///dev/ttyUSB0, tcp://host:4001 or mock://
+controllers=""     # "target=/dev/ttyUSB0 focal=tcp://host:4001" for several MRC-1, port_name is not used
+http_port="8080"
 port_rate="9600"
+record_dir="records" # CSV records of every poll, "" disables the recording
+config=""          # channel config JSON, ex. "channels.json"
+auth_file=""       # users JSON, ex. "users.json", "" allows every request
+audit_file="audit.jsonl" # append-only log of the control actions
 voltage_step="5"   # 5 -> 0.5 V
 waiting_time="500" # ms
+reply_timeout="1000" # ms, waiting for the "mrc-1>" prompt
+reconnect_max="30000" # ms, longest pause between the attempts to reopen a lost port
+poll_interval="100"  # ms, shared by all the browsers
+channel_max_voltage="0" # 1 -> 0.1 V, 0 means max_voltage, or "1000,500,0,0" per channel
+max_slew="500"       # 1 -> 0.1 V/s
+emergency_step="50"  # 50 -> 5 V, for the emergency ramp-down
+emergency_waiting_time="200" # ms
+trip_current="0"     # 1 -> 1 nA, 0 disables the trip, or "1000,1000,0,0" per channel
+trip_action="ramp-down" # log, hold, ramp-down or off
 
 # localhost server
 localhost=false # true/false
 if "${localhost}"; then
-    option="-l -p ${port_name} -r ${port_rate} -s ${voltage_step} -w ${waiting_time} -m ${max_voltage}"
+    option="-l -p ${port_name} -r ${port_rate} -s ${voltage_step} -w ${waiting_time} -m ${max_voltage} -t ${reply_timeout} --poll_interval_ms ${poll_interval} --channel_max_voltage ${channel_max_voltage} --max_slew ${max_slew} --emergency_step ${emergency_step} --emergency_waiting_time_ms ${emergency_waiting_time} --trip_current ${trip_current} --trip_action ${trip_action} --audit_file ${audit_file} --reconnect_max_ms ${reconnect_max} --http_port ${http_port}"
 else
-    option="-p ${port_name} -r ${port_rate} -s ${voltage_step} -w ${waiting_time} -m ${max_voltage}"
+    option="-p ${port_name} -r ${port_rate} -s ${voltage_step} -w ${waiting_time} -m ${max_voltage} -t ${reply_timeout} --poll_interval_ms ${poll_interval} --channel_max_voltage ${channel_max_voltage} --max_slew ${max_slew} --emergency_step ${emergency_step} --emergency_waiting_time_ms ${emergency_waiting_time} --trip_current ${trip_current} --trip_action ${trip_action} --audit_file ${audit_file} --reconnect_max_ms ${reconnect_max} --http_port ${http_port}"
+fi
+for controller in ${controllers}; do
+    option="${option} --controller ${controller}"
+done
+if [ -n "${record_dir}" ]; then
+    option="${option} --record_dir ${record_dir}"
+fi
+if [ -n "${config}" ]; then
+    option="${option} -c ${config}"
+fi
+if [ -n "${auth_file}" ]; then
+    option="${option} --auth_file ${auth_file}"
 fi
 
 # kill the existing serial port process
@@ -0,0 +1,402 @@
+use crate::auth;
+use crate::serial::{self, Priority, SERIAL};
+use crate::shared::{error_reply, json_reply, OperationError};
+use crate::{
+    audit, channel_config, check_voltage, command_state, notify_state, outcome, set_onoff,
+    set_voltage, state_snapshot, ApplyRequest, CONFIG, DATA, LIMITS, READ_ERROR_VALUE, TRIP,
+};
+use mhv4_monitor::channel::{ChannelId, DEFAULT_CONTROLLER};
+use mhv4_monitor::credentials::Role;
+use mhv4_monitor::protocol::{self, Command, CH_NUM, REG_ONOFF, REG_SETPOINT};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+
+/// A channel of "/api/v1/channels/<controller>/<bus>/<dev>/<ch>" or "/api/v1/channels/<name>",
+/// "/api/v1/channels/<bus>/<dev>/<ch>" is of the "default" controller
+#[derive(Debug, Clone)]
+pub enum ChannelKey {
+    Id(ChannelId),
+    Name(String),
+}
+
+impl fmt::Display for ChannelKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChannelKey::Id(ref id) => write!(f, "{}", id),
+            ChannelKey::Name(ref name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+/// The state of one channel, 1 -> 0.1 V and 1 -> 1 nA like the other routes
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelState {
+    pub index: usize,
+    pub controller: String,
+    pub bus: usize,
+    pub dev: usize,
+    pub ch: usize,
+    pub name: Option<String>,
+    pub setpoint: isize,
+    /// None before the first reading or after a read error
+    pub voltage: Option<isize>,
+    pub current: Option<isize>,
+    pub max_voltage: Option<isize>,
+    pub is_on: bool,
+    pub is_positive: bool,
+    pub is_tripped: bool,
+}
+
+/// The fields to change, the others are kept
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelPatch {
+    /// new setpoint, ramped with "step" every "waiting_time_ms"
+    pub voltage: Option<isize>,
+    pub on: Option<bool>,
+    pub step: Option<isize>,
+    pub waiting_time_ms: Option<u64>,
+}
+
+/// A raw command of "/api/v1/commands", sent through the serial queue of the server
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CommandRequest {
+    /// the only controller when omitted
+    pub controller: Option<String>,
+    /// "sc 0", "re 0 1 32", "se 0 1 0 100", "on 0 1" or "off 0 1"
+    pub command: String,
+}
+
+/// GET and PATCH of "/api/v1/channels", POST of "/api/v1/commands"
+pub fn routes() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let by_id = warp::path!("api" / "v1" / "channels" / String / usize / usize / usize).map(
+        |controller: String, bus, dev, ch| {
+            ChannelKey::Id(ChannelId::new(&controller, bus, dev, ch))
+        },
+    );
+    let by_default_id = warp::path!("api" / "v1" / "channels" / usize / usize / usize)
+        .map(|bus, dev, ch| ChannelKey::Id(ChannelId::new(DEFAULT_CONTROLLER, bus, dev, ch)));
+    let by_name = warp::path!("api" / "v1" / "channels" / String).map(ChannelKey::Name);
+    let channel = by_id.or(by_default_id).unify().or(by_name).unify();
+
+    let list_route = warp::path!("api" / "v1" / "channels")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .map(|_user: String| json_reply(channel_states()));
+
+    let get_route = channel
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .map(|key: ChannelKey, _user: String| {
+            json_reply(channel_index(&key).and_then(channel_state))
+        });
+
+    let patch_route = channel
+        .and(warp::patch())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
+        .and(warp::body::json())
+        .and_then(patch_channel);
+
+    let command_route = warp::path!("api" / "v1" / "commands")
+        .and(warp::post())
+        .and(auth::with_role(Role::Observer))
+        .and(warp::addr::remote())
+        .and(warp::body::json())
+        .and_then(post_command);
+
+    list_route
+        .or(get_route)
+        .unify()
+        .or(patch_route)
+        .unify()
+        .or(command_route)
+        .unify()
+}
+
+// sc and re are for the observers, se, on and off for the operators
+async fn post_command(
+    user: String,
+    address: Option<SocketAddr>,
+    request: CommandRequest,
+) -> Result<Response, Rejection> {
+    let command = match request.command.parse::<Command>() {
+        Ok(command) => command,
+        Err(e) => {
+            return Ok(json_reply::<()>(Err(OperationError::InvalidRequest(
+                e.to_string(),
+            ))))
+        }
+    };
+    let is_write = !matches!(command, Command::Scan { .. } | Command::Read { .. });
+    if is_write && !auth::has_role(&user, Role::Operator) {
+        log::warn!("Rejected: \"{}\" is not an operator", user);
+        return Ok(error_reply(
+            "forbidden",
+            String::from("Operator role required"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    let controller = match command_controller(request.controller) {
+        Ok(controller) => controller,
+        Err(e) => return Ok(json_reply::<()>(Err(e))),
+    };
+    log::info!("command \"{}\" to {} by {}", command, controller, user);
+
+    // se, on and off are written to the audit log with the user
+    let previous = command_state(&controller, &command);
+    let result = send_command(&controller, command).await;
+    if let Some(previous) = previous {
+        let payload =
+            serde_json::json!({ "controller": controller, "command": command.to_string() });
+        audit(
+            &user,
+            address,
+            "command",
+            &payload,
+            previous,
+            outcome(&result),
+        );
+        if let Err(e) = notify_state() {
+            log::error!("Error: {:?}", e);
+        }
+    }
+    Ok(json_reply(result))
+}
+
+fn command_controller(controller: Option<String>) -> Result<String, OperationError> {
+    let names = SERIAL
+        .get()
+        .ok_or(OperationError::PortGetError)?
+        .iter()
+        .map(|x| x.name().to_string())
+        .collect::<Vec<_>>();
+    match controller {
+        Some(name) if names.contains(&name) => Ok(name),
+        Some(name) => Err(OperationError::InvalidRequest(format!(
+            "Unknown controller \"{}\", one of {:?}",
+            name, names
+        ))),
+        None if names.len() == 1 => Ok(names[0].clone()),
+        None => Err(OperationError::InvalidRequest(format!(
+            "Give \"controller\", one of {:?}",
+            names
+        ))),
+    }
+}
+
+// the reads go with the polling, the writes before it
+async fn send_command(
+    controller: &str,
+    command: Command,
+) -> Result<protocol::Response, OperationError> {
+    let priority = match command {
+        Command::Scan { .. } | Command::Read { .. } => Priority::Monitor,
+        _ => {
+            check_write(controller, &command)?;
+            Priority::Control
+        }
+    };
+    Ok(serial::controller(controller)?
+        .request(command, priority)
+        .await?)
+}
+
+// only the registers which the server does not keep can be written, of the scanned modules
+fn check_write(controller: &str, command: &Command) -> Result<(), OperationError> {
+    let (bus, dev, reg) = match *command {
+        Command::Set { bus, dev, reg, .. } => (bus, dev, reg),
+        Command::On { .. } | Command::Off { .. } => {
+            return Err(OperationError::InvalidRequest(String::from(
+                "The remote control is switched by \"/status\" for all the modules",
+            )))
+        }
+        _ => return Ok(()),
+    };
+    if (REG_SETPOINT..REG_SETPOINT + CH_NUM).contains(&reg)
+        || (REG_ONOFF..REG_ONOFF + CH_NUM).contains(&reg)
+    {
+        return Err(OperationError::InvalidRequest(String::from(
+            "The setpoints and ON/OFF are changed by \"/api/v1/channels\", \"/apply\" or \"/onoff\" with the limits and the ramp",
+        )));
+    }
+    let shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+    // the ramp would be confused by the change
+    if shared_data.is_progress {
+        return Err(OperationError::RampInProgress);
+    }
+    let is_scanned = shared_data
+        .get_data()
+        .iter()
+        .any(|x| x.controller == controller && (x.bus, x.dev) == (bus, dev));
+    if !is_scanned {
+        return Err(OperationError::ChannelNotFound(format!(
+            "{}:{}:{}",
+            controller, bus, dev
+        )));
+    }
+    Ok(())
+}
+
+async fn patch_channel(
+    key: ChannelKey,
+    user: String,
+    address: Option<SocketAddr>,
+    patch: ChannelPatch,
+) -> Result<Response, Rejection> {
+    log::info!("channel {} is patched by {}", key, user);
+    let previous = state_snapshot();
+    let payload = serde_json::json!({ "channel": key.to_string(), "patch": patch });
+    let result = apply_patch(&key, patch).await;
+    audit(
+        &user,
+        address,
+        "channel",
+        &payload,
+        previous,
+        outcome(&result),
+    );
+    if let Err(e) = notify_state() {
+        log::error!("Error: {:?}", e);
+    }
+    Ok(json_reply(result))
+}
+
+// the whole lists of "/onoff" and "/apply" with only this channel changed,
+// nothing is changed when a part of the patch is refused
+async fn apply_patch(
+    key: &ChannelKey,
+    patch: ChannelPatch,
+) -> Result<ChannelState, OperationError> {
+    let index = channel_index(key)?;
+    if patch.voltage.is_none() && patch.on.is_none() {
+        return Err(OperationError::InvalidRequest(String::from(
+            "Nothing to change, give \"voltage\" and/or \"on\"",
+        )));
+    }
+
+    let request = match patch.voltage {
+        Some(voltage) => {
+            let mhv4_data_array = DATA
+                .get()
+                .ok_or(OperationError::SharedDataError)?
+                .lock()?
+                .get_data();
+            let voltages = mhv4_data_array
+                .iter()
+                .enumerate()
+                .map(|(i, x)| if i == index { voltage } else { x.get_current() })
+                .collect();
+            let request = ApplyRequest::Ramp {
+                voltages,
+                step: patch.step,
+                waiting_time_ms: patch.waiting_time_ms,
+            };
+            check_voltage(&request)?;
+            Some(request)
+        }
+        None => None,
+    };
+    if patch.on.is_some()
+        && !DATA
+            .get()
+            .ok_or(OperationError::SharedDataError)?
+            .lock()?
+            .is_rc
+    {
+        return Err(OperationError::RcOff);
+    }
+
+    if let Some(request) = request {
+        set_voltage(request).await?;
+    }
+    if let Some(on) = patch.on {
+        let mhv4_data_array = DATA
+            .get()
+            .ok_or(OperationError::SharedDataError)?
+            .lock()?
+            .get_data();
+        let arr = mhv4_data_array
+            .iter()
+            .enumerate()
+            .map(|(i, x)| if i == index { on } else { x.is_on })
+            .collect();
+        set_onoff(arr).await?;
+    }
+    channel_state(index)
+}
+
+fn channel_index(key: &ChannelKey) -> Result<usize, OperationError> {
+    let index = match *key {
+        ChannelKey::Id(ref id) => DATA
+            .get()
+            .ok_or(OperationError::SharedDataError)?
+            .lock()?
+            .get_data()
+            .iter()
+            .position(|x| x.channel_id() == *id),
+        ChannelKey::Name(ref name) => CONFIG
+            .lock()?
+            .iter()
+            .position(|x| x.as_ref().is_some_and(|x| x.name == *name)),
+    };
+    index.ok_or_else(|| OperationError::ChannelNotFound(key.to_string()))
+}
+
+fn channel_states() -> Result<Vec<ChannelState>, OperationError> {
+    let ch_num = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .get_data()
+        .len();
+    (0..ch_num).map(channel_state).collect()
+}
+
+fn channel_state(index: usize) -> Result<ChannelState, OperationError> {
+    let shared_data = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .clone();
+    let mhv4_data = shared_data
+        .get_data()
+        .get(index)
+        .cloned()
+        .ok_or(OperationError::DataGetError)?;
+    let reading = |values: &[isize]| {
+        values
+            .get(index)
+            .copied()
+            .filter(|&x| x != READ_ERROR_VALUE)
+    };
+    let is_tripped = match TRIP.get() {
+        Some(trip) => trip.lock()?.is_tripped(index),
+        None => false,
+    };
+    let max_voltage = match LIMITS.get() {
+        Some(limits) => Some(limits.lock()?.max_voltage(index)),
+        None => None,
+    };
+
+    Ok(ChannelState {
+        index,
+        controller: mhv4_data.controller.clone(),
+        bus: mhv4_data.bus,
+        dev: mhv4_data.dev,
+        ch: mhv4_data.ch,
+        name: channel_config(index).map(|x| x.name),
+        setpoint: mhv4_data.get_current(),
+        voltage: reading(&shared_data.voltages),
+        current: reading(&shared_data.currents),
+        max_voltage,
+        is_on: mhv4_data.is_on,
+        is_positive: mhv4_data.is_positive,
+        is_tripped,
+    })
+}
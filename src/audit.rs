@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// One control action, written as one JSON line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub time_ms: u64,
+    /// the authenticated user, "server" for the commands of the server itself
+    pub user: String,
+    /// address of the client, if the action was requested over HTTP
+    pub address: Option<String>,
+    /// route ("apply", "onoff", ...) or raw command ("se", "on", "off")
+    pub action: String,
+    pub payload: serde_json::Value,
+    /// the state in "SharedData" before the action
+    pub previous: serde_json::Value,
+    /// "ok" or the error message
+    pub outcome: String,
+}
+
+/// Append-only log of the control actions
+///
+/// The file is never truncated nor rewritten, every entry is synced to the disk
+/// before the next one.
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    pub fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        self.write(entry)?;
+        self.file.sync_data()
+    }
+
+    fn write(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let line = serde_json::to_string(entry)?;
+        writeln!(self.file, "{}", line)
+    }
+}
+
+/// Writes the entries on its own thread, so a slow disk does not hold the control
+///
+/// The entries queued during a sync are written together and synced once.
+pub struct AuditWriter {
+    tx: Sender<AuditEntry>,
+    thread: JoinHandle<()>,
+}
+
+impl AuditWriter {
+    pub fn start(mut audit_log: AuditLog) -> io::Result<AuditWriter> {
+        let (tx, rx) = mpsc::channel::<AuditEntry>();
+        let thread = thread::Builder::new()
+            .name(String::from("audit"))
+            .spawn(move || {
+                while let Ok(entry) = rx.recv() {
+                    let mut result = audit_log.write(&entry);
+                    for entry in rx.try_iter() {
+                        result = result.and(audit_log.write(&entry));
+                    }
+                    if let Err(e) = result.and_then(|_| audit_log.file.sync_data()) {
+                        log::error!("Could not write the audit log: {}", e);
+                    }
+                }
+            })?;
+        Ok(AuditWriter { tx, thread })
+    }
+
+    /// never waits for the disk
+    pub fn send(&self, entry: AuditEntry) {
+        if self.tx.send(entry).is_err() {
+            log::error!("The audit log is closed");
+        }
+    }
+
+    /// waits until all the entries are synced
+    pub fn close(self) {
+        drop(self.tx);
+        let _ = self.thread.join();
+    }
+}
+
+/// Conditions of "read", the entries match all of them
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub from_ms: Option<u64>,
+    pub to_ms: Option<u64>,
+    pub user: Option<String>,
+    pub action: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.from_ms.is_none_or(|x| entry.time_ms >= x)
+            && self.to_ms.is_none_or(|x| entry.time_ms <= x)
+            && self.user.as_ref().is_none_or(|x| *x == entry.user)
+            && self.action.as_ref().is_none_or(|x| *x == entry.action)
+    }
+}
+
+/// the last "limit" entries matching the filter, oldest first
+pub fn read<P: AsRef<Path>>(
+    path: P,
+    filter: &AuditFilter,
+    limit: usize,
+) -> io::Result<Vec<AuditEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // nothing is logged yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        // the last line may be cut by a crash
+        let entry = match serde_json::from_str::<AuditEntry>(&line?) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if filter.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    let start = entries.len().saturating_sub(limit);
+    Ok(entries.split_off(start))
+}
@@ -0,0 +1,119 @@
+use crate::shared::error_reply;
+use crate::AUTH;
+use base64::Engine;
+use mhv4_monitor::credentials::{Credentials, Role};
+use std::collections::HashMap;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+// the user name when the authentication is disabled
+const ANONYMOUS: &str = "anonymous";
+
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthorized,
+    Forbidden(String),
+}
+
+impl warp::reject::Reject for AuthError {}
+
+/// Passes the name of the user who has "role" or higher
+///
+/// The token is given by "Authorization: Bearer <token>", the basic auth
+/// (the token as the password) or "?token=<token>" for the EventSource.
+pub fn with_role(role: Role) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .or(warp::any().map(HashMap::new))
+                .unify(),
+        )
+        .and_then(
+            move |header: Option<String>, query: HashMap<String, String>| async move {
+                authorize(role, header, query).map_err(warp::reject::custom)
+            },
+        )
+}
+
+/// whether the user authenticated by "with_role" has "role" or higher
+pub fn has_role(user: &str, role: Role) -> bool {
+    match AUTH.get() {
+        Some(Some(credentials)) => credentials
+            .users
+            .iter()
+            .any(|x| x.name == user && x.role >= role),
+        _ => true,
+    }
+}
+
+fn authorize(
+    role: Role,
+    header: Option<String>,
+    query: HashMap<String, String>,
+) -> Result<String, AuthError> {
+    let credentials: &Credentials = match AUTH.get() {
+        Some(Some(credentials)) => credentials,
+        _ => return Ok(String::from(ANONYMOUS)),
+    };
+
+    let (name, token) = match header {
+        Some(header) => parse_header(&header).ok_or(AuthError::Unauthorized)?,
+        None => (
+            None,
+            query.get("token").cloned().ok_or(AuthError::Unauthorized)?,
+        ),
+    };
+    let user = credentials
+        .authenticate(name.as_deref(), &token)
+        .ok_or(AuthError::Unauthorized)?;
+    if user.role < role {
+        return Err(AuthError::Forbidden(user.name.clone()));
+    }
+    Ok(user.name.clone())
+}
+
+// (user name, token)
+fn parse_header(header: &str) -> Option<(Option<String>, String)> {
+    let (scheme, value) = header.trim().split_once(' ')?;
+    match scheme.to_lowercase().as_str() {
+        "bearer" => Some((None, value.trim().to_string())),
+        "basic" => {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(value.trim())
+                .ok()?;
+            let (name, token) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+            Some((Some(name.to_string()), token.to_string()))
+        }
+        _ => None,
+    }
+}
+
+impl AuthError {
+    /// 401 with the challenge, or 403 for an observer
+    pub fn reply(&self) -> Response {
+        match self {
+            AuthError::Unauthorized => {
+                log::warn!("Rejected: no valid credentials");
+                warp::reply::with_header(
+                    error_reply(
+                        "unauthorized",
+                        String::from("Authentication required"),
+                        StatusCode::UNAUTHORIZED,
+                    ),
+                    "WWW-Authenticate",
+                    "Bearer realm=\"mhv4_monitor\"",
+                )
+                .into_response()
+            }
+            AuthError::Forbidden(name) => {
+                log::warn!("Rejected: \"{}\" is not an operator", name);
+                error_reply(
+                    "forbidden",
+                    String::from("Operator role required"),
+                    StatusCode::FORBIDDEN,
+                )
+            }
+        }
+    }
+}
@@ -1,4 +1,13 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use mhv4_monitor::audit::AuditEntry;
+use mhv4_monitor::protocol::{
+    self, Command, Response, BUS_NUM, CH_NUM, LINE_END, PROMPT, REG_CURRENT, REG_POLARITY,
+    REG_READBACK, REG_SETPOINT, REG_STATUS,
+};
+use mhv4_monitor::transport::{self, Transport};
+use mhv4_monitor::{client, recorder};
+use serde_json::json;
 use std::error::Error;
 use std::io::prelude::*;
 use std::time::Duration;
@@ -12,63 +21,583 @@ use std::time::Duration;
     arg_required_else_help = true,
 )]
 struct MyArguments {
+    #[clap(subcommand)]
+    operation: Option<Operation>,
+
     #[clap(
-        value_name = "COMMAND",
-        help = "send command (String), ex. \"sc 0\"",
-        required = true
+        short = 'p',
+        long = "port_name",
+        default_value = "/dev/ttyUSB0",
+        global = true,
+        help = "\"serial:///dev/ttyUSB0\", \"tcp://host:4001\" or \"mock://\", with \"--direct\""
     )]
-    command: String,
-
-    #[clap(short = 'p', long = "port_name", default_value = "/dev/ttyUSB0")]
     port_name: String,
+
+    #[clap(
+        long = "direct",
+        global = true,
+        help = "open the port by this command instead of the running server"
+    )]
+    direct: bool,
+
+    #[clap(
+        long = "controller",
+        global = true,
+        help = "controller of the running server, not needed with only one"
+    )]
+    controller: Option<String>,
+
+    #[clap(short = 'r', long = "port_rate", default_value = "9600", global = true)]
+    port_rate: u32,
+
+    #[clap(
+        short = 't',
+        long = "reply_timeout_ms",
+        default_value = "1000",
+        global = true,
+        help = "overall time to wait for the \"mrc-1>\" prompt"
+    )]
+    reply_timeout: u64,
+
+    #[clap(
+        long = "max_voltage",
+        default_value = "3000",
+        global = true,
+        help = "highest setpoint of \"voltage\", 1 -> 0.1 V"
+    )]
+    max_voltage: isize,
+
+    #[clap(long = "json", global = true, help = "print the result as JSON")]
+    json: bool,
+
+    #[clap(
+        long = "server",
+        default_value = "http://localhost:8080",
+        global = true
+    )]
+    server: String,
+
+    #[clap(
+        long = "token",
+        global = true,
+        help = "operator token when the server requires the authentication"
+    )]
+    token: Option<String>,
+}
+
+/// One operation on the MRC-1, the registers are in their own units (1 -> 0.1 V, 1 -> 1 nA)
+#[derive(Debug, Subcommand)]
+enum Operation {
+    /// modules on the bus, "sc <bus>"
+    Scan { bus: usize },
+    /// one register, "re <bus> <dev> <reg>"
+    Read { bus: usize, dev: usize, reg: usize },
+    /// one register, "se <bus> <dev> <reg> <value>"
+    Set {
+        bus: usize,
+        dev: usize,
+        reg: usize,
+        #[clap(allow_negative_numbers = true)]
+        value: isize,
+    },
+    /// remote control of the module on, "on <bus> <dev>", with "--direct"
+    On { bus: usize, dev: usize },
+    /// remote control of the module off, "off <bus> <dev>", with "--direct"
+    Off { bus: usize, dev: usize },
+    /// setpoint and measured voltage of the channel, the setpoint is changed if given
+    /// (ramped by the server with its limits)
+    Voltage {
+        bus: usize,
+        dev: usize,
+        ch: usize,
+        value: Option<isize>,
+    },
+    /// every channel of the MHV4 modules on all the buses
+    Status,
+    /// any command as it is, ex. "sc 0"
+    Raw { command: String },
+    /// ramp down and switch off all the channels, by the running server
+    Emergency,
+    /// scan the modules again and show the channels, by the running server
+    Rescan,
+    /// the latest control actions of the running server
+    Audit {
+        #[clap(long = "limit", default_value = "20", help = "number of the entries")]
+        limit: usize,
+        #[clap(long = "user", help = "the entries of this user only")]
+        user: Option<String>,
+    },
+}
+
+// the MRC-1 through the running server or the port itself
+trait Mrc {
+    fn send(&mut self, command: Command) -> Result<Response, Box<dyn Error>>;
+
+    // the reply lines of any command
+    fn send_raw(&mut self, command: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    fn read(&mut self, bus: usize, dev: usize, reg: usize) -> Result<isize, Box<dyn Error>> {
+        self.send(Command::Read { bus, dev, reg })?
+            .value()
+            .ok_or_else(|| "no value in the reply".into())
+    }
+
+    fn set(
+        &mut self,
+        bus: usize,
+        dev: usize,
+        reg: usize,
+        value: isize,
+    ) -> Result<isize, Box<dyn Error>> {
+        self.send(Command::Set {
+            bus,
+            dev,
+            reg,
+            value,
+        })?
+        .value()
+        .ok_or_else(|| "no value in the reply".into())
+    }
+
+    fn set_voltage(
+        &mut self,
+        bus: usize,
+        dev: usize,
+        ch: usize,
+        value: isize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.set(bus, dev, ch + REG_SETPOINT, value).map(|_| ())
+    }
+}
+
+// the commands go through the serial queue of the server, "POST /api/v1/commands"
+struct Server {
+    url: String,
+    token: Option<String>,
+    controller: Option<String>,
+}
+
+impl Server {
+    fn new(args: &MyArguments) -> Server {
+        Server {
+            url: args.server.clone(),
+            token: args.token.clone(),
+            controller: args.controller.clone(),
+        }
+    }
+
+    // the only controller with the channel, when "--controller" is not given
+    fn channel_controller(
+        &self,
+        bus: usize,
+        dev: usize,
+        ch: usize,
+    ) -> Result<String, Box<dyn Error>> {
+        if let Some(ref controller) = self.controller {
+            return Ok(controller.clone());
+        }
+        let (status, body) = client::request(
+            &self.url,
+            "GET",
+            "/api/v1/channels",
+            None,
+            self.token.as_deref(),
+        )?;
+        if status != 200 {
+            return Err(format!("channels: {} {}", status, body).into());
+        }
+        let controllers = serde_json::from_str::<Vec<serde_json::Value>>(&body)?
+            .into_iter()
+            .filter(|x| x["bus"] == bus && x["dev"] == dev && x["ch"] == ch)
+            .filter_map(|x| x["controller"].as_str().map(|x| x.to_string()))
+            .collect::<Vec<_>>();
+        match controllers.as_slice() {
+            [controller] => Ok(controller.clone()),
+            [] => Err(format!("no channel {}:{}:{} in the server", bus, dev, ch).into()),
+            _ => Err(format!("give \"--controller\", one of {:?}", controllers).into()),
+        }
+    }
+}
+
+impl Mrc for Server {
+    fn send(&mut self, command: Command) -> Result<Response, Box<dyn Error>> {
+        let body = json!({ "controller": self.controller, "command": command.to_string() });
+        let (status, body) = client::request(
+            &self.url,
+            "POST",
+            "/api/v1/commands",
+            Some(&body.to_string()),
+            self.token.as_deref(),
+        )
+        .map_err(|e| format!("{}: {} (use \"--direct\" without the server)", self.url, e))?;
+        if status != 200 {
+            return Err(format!("{}: {} {}", command, status, body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    // only the commands known by the server
+    fn send_raw(&mut self, command: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let command = command
+            .parse::<Command>()
+            .map_err(|e| format!("{} (use \"--direct\" for the others)", e))?;
+        let lines = match self.send(command)? {
+            // "0: 27, ON" like the MRC-1, without the empty addresses
+            Response::Scan(modules) => modules
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{}: {}, {}",
+                        x.dev,
+                        x.idc,
+                        if x.is_on { "ON" } else { "OFF" }
+                    )
+                })
+                .collect(),
+            Response::Value(value) => vec![value.to_string()],
+            Response::Done => Vec::new(),
+        };
+        Ok(lines)
+    }
+
+    // ramped with the limits, "PATCH /api/v1/channels/<controller>/<bus>/<dev>/<ch>"
+    fn set_voltage(
+        &mut self,
+        bus: usize,
+        dev: usize,
+        ch: usize,
+        value: isize,
+    ) -> Result<(), Box<dyn Error>> {
+        let controller = self.channel_controller(bus, dev, ch)?;
+        let path = format!("/api/v1/channels/{}/{}/{}/{}", controller, bus, dev, ch);
+        let body = json!({ "voltage": value }).to_string();
+        let (status, body) = client::request(
+            &self.url,
+            "PATCH",
+            &path,
+            Some(&body),
+            self.token.as_deref(),
+        )?;
+        if status != 200 {
+            return Err(format!("voltage: {} {}", status, body).into());
+        }
+        Ok(())
+    }
+}
+
+// the port is opened by this command, so the server should not be running on it
+struct Direct {
+    port: Box<dyn Transport>,
+    reply_timeout: Duration,
+}
+
+impl Direct {
+    fn open(args: &MyArguments) -> Result<Direct, Box<dyn Error>> {
+        Ok(Direct {
+            port: transport::open(&args.port_name, args.port_rate, Duration::from_millis(100))?,
+            reply_timeout: Duration::from_millis(args.reply_timeout),
+        })
+    }
+}
+
+impl Mrc for Direct {
+    // the reply lines between the echo and the prompt
+    fn send_raw(&mut self, command: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.port.clear_input()?;
+        self.port.write_all(format!("{}\r", command).as_bytes())?;
+        let reply = transport::read_until_prompt(self.port.as_mut(), self.reply_timeout, 4096)?;
+        let lines = reply
+            .split(LINE_END)
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty() && *x != PROMPT)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        // the echo of the command
+        Ok(lines.into_iter().skip(1).collect())
+    }
+
+    fn send(&mut self, command: Command) -> Result<Response, Box<dyn Error>> {
+        self.port.clear_input()?;
+        self.port.write_all(command.to_wire().as_bytes())?;
+        let reply = transport::read_until_prompt(self.port.as_mut(), self.reply_timeout, 4096)?;
+        Ok(protocol::parse_response(&command, &reply)?)
+    }
 }
 
-#[allow(unused_assignments)]
 fn main() -> Result<(), Box<dyn Error>> {
     let args = MyArguments::parse();
 
-    let mut port = serialport::new(args.port_name, 9600)
-        .stop_bits(serialport::StopBits::One)
-        .data_bits(serialport::DataBits::Eight)
-        .parity(serialport::Parity::None)
-        .timeout(Duration::from_millis(100))
-        .open()?;
-
-    let mut buf: Vec<u8> = vec![0; 1000];
-    let mut vec: Vec<String> = Vec::new();
-
-    let send_str = format!("{}\r", args.command);
-
-    match port.write(send_str.as_bytes()) {
-        Ok(_) => std::io::stdout().flush()?,
-        Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-        Err(e) => eprintln!("{:?}", e),
-    }
-    std::thread::sleep(Duration::from_millis(100));
-
-    // read from the serial port
-    match port.read(buf.as_mut_slice()) {
-        Ok(t) => {
-            let bytes = &buf[..t];
-            let string = String::from_utf8(bytes.to_vec())?;
-            let v = string.split("\n\r").collect::<Vec<_>>();
-            vec = v.iter().map(|&s| s.to_string()).collect();
-            if vec.len() > 2 {
-                println!("send command: {}", vec[0]);
-                vec.remove(0);
-                vec.pop();
-                println!("Result:");
-                println!("{:?}", vec);
+    let operation = match args.operation {
+        Some(ref operation) => operation,
+        None => MyArguments::command()
+            .error(
+                ErrorKind::MissingSubcommand,
+                "an operation is required, ex. \"scan 0\"",
+            )
+            .exit(),
+    };
+    let is_server_only = matches!(
+        *operation,
+        Operation::Emergency | Operation::Rescan | Operation::Audit { .. }
+    );
+    if args.direct && is_server_only {
+        MyArguments::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "this operation is sent to the running server, without \"--direct\"",
+            )
+            .exit()
+    }
+    if args.direct {
+        run(&mut Direct::open(&args)?, operation, &args)
+    } else {
+        run(&mut Server::new(&args), operation, &args)
+    }
+}
+
+fn run(mrc: &mut dyn Mrc, operation: &Operation, args: &MyArguments) -> Result<(), Box<dyn Error>> {
+    let (value, text) = match *operation {
+        Operation::Scan { bus } => {
+            let modules = match mrc.send(Command::Scan { bus })? {
+                Response::Scan(modules) => modules,
+                _ => return Err("no modules in the reply".into()),
+            };
+            let value = modules
+                .iter()
+                .map(|x| json!({ "dev": x.dev, "idc": x.idc, "is_on": x.is_on }))
+                .collect::<Vec<_>>();
+            let text = modules
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{}: IDC {}, {}",
+                        x.dev,
+                        x.idc,
+                        if x.is_on { "ON" } else { "OFF" }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            (json!(value), text)
+        }
+        Operation::Read { bus, dev, reg } => {
+            let value = mrc.read(bus, dev, reg)?;
+            (
+                json!({ "bus": bus, "dev": dev, "reg": reg, "value": value }),
+                value.to_string(),
+            )
+        }
+        Operation::Set {
+            bus,
+            dev,
+            reg,
+            value,
+        } => {
+            let value = mrc.set(bus, dev, reg, value)?;
+            (
+                json!({ "bus": bus, "dev": dev, "reg": reg, "value": value }),
+                value.to_string(),
+            )
+        }
+        Operation::On { bus, dev } | Operation::Off { bus, dev } => {
+            let is_on = matches!(*operation, Operation::On { .. });
+            let command = if is_on {
+                Command::On { bus, dev }
             } else {
-                println!("read error!");
-                println!("{:?}", vec);
+                Command::Off { bus, dev }
+            };
+            mrc.send(command)?;
+            (
+                json!({ "bus": bus, "dev": dev, "is_on": is_on }),
+                format!("{}: done", command),
+            )
+        }
+        Operation::Voltage {
+            bus,
+            dev,
+            ch,
+            value,
+        } => {
+            if ch >= CH_NUM {
+                return Err(format!("no such channel {}", ch).into());
+            }
+            if let Some(value) = value {
+                if !(0..=args.max_voltage).contains(&value) {
+                    return Err(format!(
+                        "{} is out of the range from 0 to {} (1 -> 0.1 V)",
+                        value, args.max_voltage
+                    )
+                    .into());
+                }
+                mrc.set_voltage(bus, dev, ch, value)?;
             }
-            Ok(())
+            let setpoint = mrc.read(bus, dev, ch + REG_SETPOINT)?;
+            let voltage = mrc.read(bus, dev, ch + REG_READBACK)?;
+            (
+                json!({
+                    "bus": bus,
+                    "dev": dev,
+                    "ch": ch,
+                    "setpoint": setpoint,
+                    "voltage": voltage,
+                }),
+                format!(
+                    "{}:{}:{} setpoint {:.1} V, voltage {:.1} V",
+                    bus,
+                    dev,
+                    ch,
+                    setpoint as f64 * 0.1,
+                    voltage as f64 * 0.1
+                ),
+            )
+        }
+        Operation::Status => status(mrc)?,
+        Operation::Raw { ref command } => {
+            let lines = mrc.send_raw(command)?;
+            (
+                json!({ "command": command, "reply": lines }),
+                lines.join("\n"),
+            )
         }
-        Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(()),
-        Err(e) => {
-            eprintln!("{:?}", e);
-            Err(Box::new(e))
+        Operation::Emergency => emergency(args)?,
+        Operation::Rescan => rescan(args)?,
+        Operation::Audit { limit, ref user } => audit(args, limit, user.as_deref())?,
+    };
+
+    if args.json {
+        println!("{}", value);
+    } else if !text.is_empty() {
+        println!("{}", text);
+    }
+    Ok(())
+}
+
+// every channel of the MHV4 modules, as JSON and as a table
+fn status(mrc: &mut dyn Mrc) -> Result<(serde_json::Value, String), Box<dyn Error>> {
+    let mut channels = Vec::new();
+    let mut lines = vec![String::from(
+        "bus dev ch idc rc  on  pol setpoint(V) voltage(V) current(uA)",
+    )];
+    for bus in 0..BUS_NUM {
+        let modules = match mrc.send(Command::Scan { bus })? {
+            Response::Scan(modules) => modules,
+            _ => return Err("no modules in the reply".into()),
+        };
+        for module in modules {
+            if module.idc != 27 && module.idc != 17 {
+                continue;
+            }
+            let dev = module.dev;
+            for ch in 0..CH_NUM {
+                let is_on = mrc.read(bus, dev, ch + REG_STATUS)? == 1;
+                let is_positive = mrc.read(bus, dev, ch + REG_POLARITY)? == 1;
+                let setpoint = mrc.read(bus, dev, ch + REG_SETPOINT)?;
+                let voltage = mrc.read(bus, dev, ch + REG_READBACK)?;
+                let current = mrc.read(bus, dev, ch + REG_CURRENT)?;
+                lines.push(format!(
+                    "{:>3} {:>3} {:>2} {:>3} {:<3} {:<3} {:<3} {:>11.1} {:>10.1} {:>11.3}",
+                    bus,
+                    dev,
+                    ch,
+                    module.idc,
+                    if module.is_on { "ON" } else { "OFF" },
+                    if is_on { "ON" } else { "OFF" },
+                    if is_positive { "+" } else { "-" },
+                    setpoint as f64 * 0.1,
+                    voltage as f64 * 0.1,
+                    current as f64 * 0.001
+                ));
+                channels.push(json!({
+                    "bus": bus,
+                    "dev": dev,
+                    "ch": ch,
+                    "idc": module.idc,
+                    "is_rc": module.is_on,
+                    "is_on": is_on,
+                    "is_positive": is_positive,
+                    "setpoint": setpoint,
+                    "voltage": voltage,
+                    "current": current,
+                }));
+            }
         }
     }
+    Ok((json!(channels), lines.join("\n")))
+}
+
+// one request to the running server, the body of the reply
+fn server_request(
+    args: &MyArguments,
+    method: &str,
+    path: &str,
+    name: &str,
+) -> Result<String, Box<dyn Error>> {
+    let (status, body) = client::request(&args.server, method, path, None, args.token.as_deref())?;
+    if !(200..300).contains(&status) {
+        return Err(format!("{}: {} {}", name, status, body).into());
+    }
+    Ok(body)
+}
+
+fn emergency(args: &MyArguments) -> Result<(serde_json::Value, String), Box<dyn Error>> {
+    server_request(args, "POST", "/emergency", "emergency ramp-down")?;
+    Ok((
+        json!({ "emergency": true }),
+        String::from("emergency ramp-down is started"),
+    ))
+}
+
+// the channels found by the rescan
+fn rescan(args: &MyArguments) -> Result<(serde_json::Value, String), Box<dyn Error>> {
+    server_request(args, "POST", "/rescan", "rescan")?;
+    let body = server_request(args, "GET", "/api/v1/channels", "channels")?;
+    let channels = serde_json::from_str::<Vec<serde_json::Value>>(&body)?;
+    let text = channels
+        .iter()
+        .map(|x| {
+            format!(
+                "{}:{}:{}:{} {}",
+                x["controller"].as_str().unwrap_or(""),
+                x["bus"],
+                x["dev"],
+                x["ch"],
+                x["name"].as_str().unwrap_or("")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((json!(channels), text))
+}
+
+fn audit(
+    args: &MyArguments,
+    limit: usize,
+    user: Option<&str>,
+) -> Result<(serde_json::Value, String), Box<dyn Error>> {
+    let mut path = format!("/audit?limit={}", limit);
+    if let Some(user) = user {
+        path.push_str(&format!("&user={}", client::encode(user)));
+    }
+    let body = server_request(args, "GET", &path, "audit log")?;
+    let entries = serde_json::from_str::<Vec<AuditEntry>>(&body)?;
+    let text = entries
+        .iter()
+        .map(|x| {
+            format!(
+                "{} {}{} {} {} (previous {}) -> {}",
+                recorder::format_time(x.time_ms),
+                x.user,
+                x.address
+                    .as_ref()
+                    .map(|x| format!("@{}", x))
+                    .unwrap_or_default(),
+                x.action,
+                x.payload,
+                x.previous,
+                x.outcome
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((json!(entries), text))
 }
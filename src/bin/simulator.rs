@@ -0,0 +1,63 @@
+use clap::Parser;
+use mhv4_monitor::simulator::{self, SimModule, Simulator};
+use serialport::{SerialPort, TTYPort};
+use std::error::Error;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "mrc_simulator",
+    version = env!("CARGO_PKG_VERSION"),
+    author = env!("CARGO_PKG_AUTHORS"),
+    about = "MRC-1 / MHV4 simulator on a pseudo-terminal",
+)]
+struct MyArguments {
+    #[clap(
+        short = 'm',
+        long = "module",
+        value_name = "BUS:DEV:IDC",
+        value_parser = simulator::parse_module_spec,
+        help = "add a MHV4 module, ex. \"0:3:27\" (default: 0:0:27 and 0:1:17)"
+    )]
+    modules: Vec<(usize, usize, usize)>,
+
+    #[clap(long = "rc", help = "start the modules in the remote control mode")]
+    is_rc: bool,
+
+    #[clap(
+        short = 'l',
+        long = "link",
+        value_name = "PATH",
+        help = "make a symbolic link to the pty, ex. /tmp/ttyMRC"
+    )]
+    link: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    pretty_env_logger::init();
+    let args = MyArguments::parse();
+
+    let mut sim = Simulator::from_spec(simulator::DEFAULT_MODULES)?;
+    if !args.modules.is_empty() {
+        sim = Simulator::new();
+        for (bus, dev, idc) in args.modules {
+            sim.add_module(bus, dev, SimModule::new(idc));
+        }
+    }
+    if args.is_rc {
+        sim.set_rc_all(true);
+    }
+
+    // the slave side is kept open, otherwise the master read fails while no client is connected
+    let (mut master, slave) = TTYPort::pair()?;
+    let slave_name = slave.name().ok_or("could not get the pty name")?;
+    if let Some(link) = &args.link {
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink(&slave_name, link)?;
+        println!("simulator is listening on {} ({})", link, slave_name);
+    } else {
+        println!("simulator is listening on {}", slave_name);
+    }
+
+    simulator::serve(&mut sim, &mut master)?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The controller of the channels given without one, ex. "0:1:2" and the config entries
+pub const DEFAULT_CONTROLLER: &str = "default";
+
+/// (controller, bus, dev, ch), "target:0:1:2" as a text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChannelId {
+    pub controller: String,
+    pub bus: usize,
+    pub dev: usize,
+    pub ch: usize,
+}
+
+impl ChannelId {
+    pub fn new(controller: &str, bus: usize, dev: usize, ch: usize) -> ChannelId {
+        ChannelId {
+            controller: controller.to_string(),
+            bus,
+            dev,
+            ch,
+        }
+    }
+}
+
+impl fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.controller, self.bus, self.dev, self.ch
+        )
+    }
+}
+
+/// Error of parsing "controller:bus:dev:ch" or "bus:dev:ch"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseChannelIdError(pub String);
+
+impl fmt::Display for ParseChannelIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid channel \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for ParseChannelIdError {}
+
+impl FromStr for ChannelId {
+    type Err = ParseChannelIdError;
+
+    fn from_str(s: &str) -> Result<ChannelId, ParseChannelIdError> {
+        let invalid = || ParseChannelIdError(s.to_string());
+        let (controller, address) = match s.split(':').count() {
+            3 => (DEFAULT_CONTROLLER, s),
+            4 => s.split_once(':').ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        if controller.is_empty() {
+            return Err(invalid());
+        }
+        let nums = address
+            .split(':')
+            .map(|x| x.trim().parse::<usize>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match nums.as_slice() {
+            &[bus, dev, ch] => Ok(ChannelId::new(controller, bus, dev, ch)),
+            _ => Err(invalid()),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Sends one HTTP/1.1 request to the running server, returns the status code and the body
+///
+/// "token" is sent as "Authorization: Bearer <token>" when the server requires the authentication.
+pub fn request(
+    server: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    token: Option<&str>,
+) -> Result<(u16, String), Error> {
+    let host = server
+        .strip_prefix("http://")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "only http:// is supported"))?
+        .trim_end_matches('/');
+    let mut stream = TcpStream::connect(address(host))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    let body = body.unwrap_or("");
+    let authorization = token
+        .map(|x| format!("Authorization: Bearer {}\r\n", x))
+        .unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        host,
+        authorization,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (header, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no HTTP header"))?;
+    let status = header
+        .split_whitespace()
+        .nth(1)
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no HTTP status"))?;
+    Ok((status, body.to_string()))
+}
+
+/// "host:port" with the port 80 if not given, an IPv6 address is in brackets, ex. "[::1]:8080"
+pub fn address(host: &str) -> String {
+    let has_port = match host.rsplit_once(':') {
+        Some((name, _)) if host.starts_with('[') => name.ends_with(']'),
+        Some((name, _)) => !name.contains(':'),
+        None => false,
+    };
+    match host {
+        _ if has_port => host.to_string(),
+        _ if host.starts_with('[') => format!("{}:80", host),
+        // an IPv6 address without the brackets
+        _ if host.contains(':') => format!("[{}]:80", host),
+        _ => format!("{}:80", host),
+    }
+}
+
+/// Percent-encodes a value of the query string, ex. "a b&c" -> "a%20b%26c"
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (x as char).to_string()
+            }
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
@@ -0,0 +1,173 @@
+use crate::channel::{ChannelId, DEFAULT_CONTROLLER};
+use crate::protocol::{BUS_NUM, CH_NUM, DEV_NUM};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+/// One detector connected to (controller, bus, dev, ch), voltages in 0.1 V as the registers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    #[serde(default = "default_controller")]
+    pub controller: String,
+    pub bus: usize,
+    pub dev: usize,
+    pub ch: usize,
+    pub name: String,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub polarity: Option<Polarity>,
+    #[serde(default)]
+    pub operating_voltage: Option<isize>,
+    #[serde(default)]
+    pub max_voltage: Option<isize>,
+    #[serde(default)]
+    pub current_limit: Option<isize>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+fn default_controller() -> String {
+    DEFAULT_CONTROLLER.to_string()
+}
+
+impl ChannelConfig {
+    pub fn channel_id(&self) -> ChannelId {
+        ChannelId::new(&self.controller, self.bus, self.dev, self.ch)
+    }
+}
+
+/// The channel configuration file, ex. "channels.json"
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub channels: Vec<ChannelConfig>,
+}
+
+/// A channel found by the scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedChannel {
+    pub id: ChannelId,
+    pub is_positive: bool,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| ConfigError::Io(format!("{}: {}", path.as_ref().display(), e)))?;
+        Config::parse(&text)
+    }
+
+    /// parses and checks the entries themselves
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let config: Config =
+            serde_json::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+
+        for (i, channel) in config.channels.iter().enumerate() {
+            let id = channel.channel_id();
+            if id.bus >= BUS_NUM || id.dev >= DEV_NUM || id.ch >= CH_NUM {
+                return Err(ConfigError::Invalid(format!(
+                    "\"{}\" has no such address {}",
+                    channel.name, id
+                )));
+            }
+            let is_duplicated = config.channels[..i]
+                .iter()
+                .any(|x| x.channel_id() == id || x.name == channel.name);
+            if is_duplicated {
+                return Err(ConfigError::Invalid(format!(
+                    "\"{}\" ({}) is defined twice",
+                    channel.name, id
+                )));
+            }
+            if let (Some(operating), Some(max)) = (channel.operating_voltage, channel.max_voltage) {
+                if operating > max {
+                    return Err(ConfigError::Invalid(format!(
+                        "operating voltage of \"{}\" is over its maximum",
+                        channel.name
+                    )));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn find(&self, id: &ChannelId) -> Option<&ChannelConfig> {
+        self.channels.iter().find(|x| x.channel_id() == *id)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&ChannelConfig> {
+        self.channels.iter().find(|x| x.name == name)
+    }
+
+    /// every configured detector should be found with the expected polarity
+    pub fn check(&self, scanned: &[ScannedChannel]) -> Result<(), ConfigError> {
+        check_channels(self.channels.iter(), scanned)
+    }
+
+    /// the same as "check" for the detectors of one controller, the others may not be scanned yet
+    pub fn check_controller(
+        &self,
+        controller: &str,
+        scanned: &[ScannedChannel],
+    ) -> Result<(), ConfigError> {
+        let channels = self.channels.iter().filter(|x| x.controller == controller);
+        check_channels(channels, scanned)
+    }
+}
+
+fn check_channels<'a>(
+    channels: impl Iterator<Item = &'a ChannelConfig>,
+    scanned: &[ScannedChannel],
+) -> Result<(), ConfigError> {
+    for channel in channels {
+        let id = channel.channel_id();
+        let found = scanned.iter().find(|x| x.id == id).ok_or_else(|| {
+            ConfigError::Mismatch(format!(
+                "\"{}\" ({}) is not found by the scan",
+                channel.name, id
+            ))
+        })?;
+        if let Some(polarity) = channel.polarity {
+            if found.is_positive != (polarity == Polarity::Positive) {
+                return Err(ConfigError::Mismatch(format!(
+                    "polarity of \"{}\" ({}) is not {:?}",
+                    channel.name, id, polarity
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+    Mismatch(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref msg) => write!(f, "Could not read the config: {}", msg),
+            ConfigError::Parse(ref msg) => write!(f, "Could not parse the config: {}", msg),
+            ConfigError::Invalid(ref msg) => write!(f, "Invalid config: {}", msg),
+            ConfigError::Mismatch(ref msg) => {
+                write!(f, "Config does not match the modules: {}", msg)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
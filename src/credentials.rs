@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Observers can only read, operators can also control the modules
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Observer,
+    Operator,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    /// sent as "Authorization: Bearer <token>", or as the password of the basic auth
+    #[serde(skip_serializing)]
+    pub token: String,
+}
+
+/// The credentials file, ex. "users.json"
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub users: Vec<User>,
+}
+
+// short tokens are easy to guess
+const MIN_TOKEN_LEN: usize = 16;
+
+impl Credentials {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Credentials, CredentialsError> {
+        let text = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| CredentialsError::Io(format!("{}: {}", path.as_ref().display(), e)))?;
+        Credentials::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Credentials, CredentialsError> {
+        let credentials: Credentials =
+            serde_json::from_str(text).map_err(|e| CredentialsError::Parse(e.to_string()))?;
+
+        for (i, user) in credentials.users.iter().enumerate() {
+            if user.token.len() < MIN_TOKEN_LEN {
+                return Err(CredentialsError::Invalid(format!(
+                    "token of \"{}\" should be {} characters or more",
+                    user.name, MIN_TOKEN_LEN
+                )));
+            }
+            let is_duplicated = credentials.users[..i]
+                .iter()
+                .any(|x| x.name == user.name || x.token == user.token);
+            if is_duplicated {
+                return Err(CredentialsError::Invalid(format!(
+                    "\"{}\" or its token is defined twice",
+                    user.name
+                )));
+            }
+        }
+        Ok(credentials)
+    }
+
+    /// the user of the token, the name is also checked if given (basic auth)
+    pub fn authenticate(&self, name: Option<&str>, token: &str) -> Option<&User> {
+        self.users.iter().find(|user| {
+            name.is_none_or(|name| name == user.name) && constant_time_eq(&user.token, token)
+        })
+    }
+}
+
+// the time does not tell how many characters matched
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for CredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CredentialsError::Io(ref msg) => write!(f, "Could not read the credentials: {}", msg),
+            CredentialsError::Parse(ref msg) => {
+                write!(f, "Could not parse the credentials: {}", msg)
+            }
+            CredentialsError::Invalid(ref msg) => write!(f, "Invalid credentials: {}", msg),
+        }
+    }
+}
+
+impl Error for CredentialsError {}
@@ -0,0 +1,171 @@
+use crate::channel::ChannelId;
+use crate::recorder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+// (time_ms, value)
+type Points = Vec<(u64, isize)>;
+
+/// How the history is reduced to the requested number of points
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Downsampling {
+    /// min and max of each time bucket, keeps every spike
+    #[default]
+    MinMax,
+    /// largest triangle three buckets, keeps the visual shape
+    Lttb,
+}
+
+/// One value series of one channel, "time_ms" and "value" have the same length
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Series {
+    pub time_ms: Vec<u64>,
+    pub value: Vec<isize>,
+}
+
+impl Series {
+    fn from_points(points: Points) -> Series {
+        let (time_ms, value) = points.into_iter().unzip();
+        Series { time_ms, value }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelHistory {
+    pub controller: String,
+    pub bus: usize,
+    pub dev: usize,
+    pub ch: usize,
+    pub voltage: Series,
+    pub current: Series,
+}
+
+/// Reads the records of the channels from "from_ms" to "to_ms" (all the channels if empty),
+/// each series is reduced to "max_points" at most
+pub fn query<P: AsRef<Path>>(
+    dir: P,
+    channels: &[ChannelId],
+    from_ms: u64,
+    to_ms: u64,
+    max_points: usize,
+    method: Downsampling,
+) -> io::Result<Vec<ChannelHistory>> {
+    let mut raw: BTreeMap<ChannelId, (Points, Points)> = BTreeMap::new();
+    for path in recorder::record_files(dir)? {
+        // the file ends at the last modification
+        let is_overlapped = recorder::file_start_ms(&path).is_none_or(|x| x <= to_ms)
+            && recorder::modified_ms(&path)? >= from_ms;
+        if !is_overlapped {
+            continue;
+        }
+
+        let (file_channels, records) = recorder::read_file(&path)?;
+        for (i, id) in file_channels.into_iter().enumerate() {
+            if !channels.is_empty() && !channels.contains(&id) {
+                continue;
+            }
+            let (voltages, currents) = raw.entry(id).or_default();
+            for record in records
+                .iter()
+                .filter(|x| x.time_ms >= from_ms && x.time_ms <= to_ms)
+            {
+                if let Some(voltage) = record.voltages[i] {
+                    voltages.push((record.time_ms, voltage));
+                }
+                if let Some(current) = record.currents[i] {
+                    currents.push((record.time_ms, current));
+                }
+            }
+        }
+    }
+
+    let reduce = |points: Points| {
+        Series::from_points(match method {
+            Downsampling::MinMax => min_max(&points, max_points),
+            Downsampling::Lttb => lttb(&points, max_points),
+        })
+    };
+    Ok(raw
+        .into_iter()
+        .map(|(id, (voltages, currents))| ChannelHistory {
+            controller: id.controller,
+            bus: id.bus,
+            dev: id.dev,
+            ch: id.ch,
+            voltage: reduce(voltages),
+            current: reduce(currents),
+        })
+        .collect())
+}
+
+/// min and max of "threshold / 2" buckets of the same time span, in time order
+pub fn min_max(points: &[(u64, isize)], threshold: usize) -> Points {
+    let bucket_num = threshold / 2;
+    if points.len() <= threshold || bucket_num == 0 {
+        return points.to_vec();
+    }
+    let first = points[0].0;
+    let span = points[points.len() - 1].0 - first + 1;
+
+    let mut result: Points = Vec::new();
+    let buckets = points.chunk_by(|a, b| {
+        (a.0 - first) as u128 * bucket_num as u128 / span as u128
+            == (b.0 - first) as u128 * bucket_num as u128 / span as u128
+    });
+    for bucket in buckets {
+        let min = bucket.iter().min_by_key(|x| x.1).copied();
+        let max = bucket.iter().max_by_key(|x| x.1).copied();
+        match (min, max) {
+            (Some(min), Some(max)) if min.0 == max.0 => result.push(min),
+            (Some(min), Some(max)) if min.0 < max.0 => result.extend([min, max]),
+            (Some(min), Some(max)) => result.extend([max, min]),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// largest triangle three buckets (Sveinn Steinarsson, 2013)
+pub fn lttb(points: &[(u64, isize)], threshold: usize) -> Points {
+    if threshold >= points.len() || threshold < 3 {
+        return points.to_vec();
+    }
+
+    let mut result: Points = Vec::with_capacity(threshold);
+    // the first and the last points are always kept
+    let every = (points.len() - 2) as f64 / (threshold - 2) as f64;
+    let mut a = 0;
+    result.push(points[0]);
+
+    for i in 0..threshold - 2 {
+        // average of the next bucket
+        let next_start = ((i + 1) as f64 * every) as usize + 1;
+        let next_end = (((i + 2) as f64 * every) as usize + 1).min(points.len());
+        let next = &points[next_start..next_end];
+        let avg_x = next.iter().map(|x| x.0 as f64).sum::<f64>() / next.len() as f64;
+        let avg_y = next.iter().map(|x| x.1 as f64).sum::<f64>() / next.len() as f64;
+
+        // the point of this bucket with the largest triangle
+        let start = (i as f64 * every) as usize + 1;
+        let end = ((i + 1) as f64 * every) as usize + 1;
+        let (ax, ay) = (points[a].0 as f64, points[a].1 as f64);
+        let mut max_area = -1.0;
+        let mut selected = start;
+        for (j, point) in points.iter().enumerate().take(end).skip(start) {
+            let area =
+                ((ax - avg_x) * (point.1 as f64 - ay) - (ax - point.0 as f64) * (avg_y - ay)).abs();
+            if area > max_area {
+                max_area = area;
+                selected = j;
+            }
+        }
+        result.push(points[selected]);
+        a = selected;
+    }
+
+    result.push(points[points.len() - 1]);
+    result
+}
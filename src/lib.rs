@@ -0,0 +1,24 @@
+pub mod audit;
+pub mod channel;
+pub mod client;
+pub mod config;
+pub mod credentials;
+pub mod history;
+pub mod limits;
+pub mod link;
+pub mod protocol;
+pub mod ramping;
+pub mod recorder;
+pub mod simulator;
+pub mod transport;
+pub mod trip;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// milliseconds since the UNIX epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt;
+
+/// Hard limits of the setpoints and the ramp speed, checked before any "se" command
+///
+/// Voltages are in 0.1 V (the register unit) and the slew rates in 0.1 V/s.
+#[derive(Debug, Clone)]
+pub struct VoltageLimits {
+    max_voltage: isize,
+    channel_max: Vec<Option<isize>>,
+    min_slew: isize,
+    max_slew: isize,
+}
+
+impl VoltageLimits {
+    pub fn new(
+        in_max_voltage: isize,
+        in_channel_max: Vec<Option<isize>>,
+        in_min_slew: isize,
+        in_max_slew: isize,
+    ) -> VoltageLimits {
+        VoltageLimits {
+            max_voltage: in_max_voltage,
+            channel_max: in_channel_max,
+            min_slew: in_min_slew,
+            max_slew: in_max_slew,
+        }
+    }
+
+    /// the lower of the global and the channel maximum
+    pub fn max_voltage(&self, index: usize) -> isize {
+        match self.channel_max.get(index) {
+            Some(&Some(channel_max)) => channel_max.min(self.max_voltage),
+            _ => self.max_voltage,
+        }
+    }
+
+    pub fn check_setpoints(&self, targets: &[isize]) -> Result<(), LimitError> {
+        if targets.len() != self.channel_max.len() {
+            return Err(LimitError::ChannelNumber {
+                expected: self.channel_max.len(),
+                found: targets.len(),
+            });
+        }
+        for (index, &target) in targets.iter().enumerate() {
+            let max = self.max_voltage(index);
+            if target < 0 || target > max {
+                return Err(LimitError::Voltage { index, target, max });
+            }
+        }
+        Ok(())
+    }
+
+    /// one "step" every "waiting_time" ms
+    pub fn check_slew(&self, step: isize, waiting_time: u64) -> Result<(), LimitError> {
+        if step <= 0 || waiting_time == 0 {
+            return Err(LimitError::InvalidRamp { step, waiting_time });
+        }
+        let slew = slew_rate(step, waiting_time);
+        if slew < self.min_slew || slew > self.max_slew {
+            return Err(LimitError::Slew {
+                slew,
+                min: self.min_slew,
+                max: self.max_slew,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// in 0.1 V/s
+pub fn slew_rate(step: isize, waiting_time: u64) -> isize {
+    (step as i64 * 1000 / waiting_time.max(1) as i64) as isize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    ChannelNumber {
+        expected: usize,
+        found: usize,
+    },
+    Voltage {
+        index: usize,
+        target: isize,
+        max: isize,
+    },
+    InvalidRamp {
+        step: isize,
+        waiting_time: u64,
+    },
+    Slew {
+        slew: isize,
+        min: isize,
+        max: isize,
+    },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitError::ChannelNumber { expected, found } => write!(
+                f,
+                "{} setpoints are given, but there are {} channels",
+                found, expected
+            ),
+            LimitError::Voltage { index, target, max } => write!(
+                f,
+                "Setpoint of channel {} is {:.1} V, it should be from 0 to {:.1} V",
+                index,
+                target as f64 * 0.1,
+                max as f64 * 0.1
+            ),
+            LimitError::InvalidRamp { step, waiting_time } => {
+                write!(f, "Invalid ramp: step {} every {} ms", step, waiting_time)
+            }
+            LimitError::Slew { slew, min, max } => write!(
+                f,
+                "Ramp speed {:.1} V/s is out of the range from {:.1} to {:.1} V/s",
+                slew as f64 * 0.1,
+                min as f64 * 0.1,
+                max as f64 * 0.1
+            ),
+        }
+    }
+}
+
+impl Error for LimitError {}
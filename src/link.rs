@@ -0,0 +1,399 @@
+use crate::protocol::{self, Command, ProtocolError, Response};
+use crate::transport::{self, Transport, TransportError};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+
+/// Control commands (se, on, off) are sent before the routine monitoring reads,
+/// and the emergency switch-off before everything else
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Emergency,
+    Control,
+    Monitor,
+}
+
+struct Request {
+    command: Command,
+    reply: oneshot::Sender<Result<Response, LinkError>>,
+}
+
+#[derive(Default)]
+struct Queues {
+    emergency: VecDeque<Request>,
+    control: VecDeque<Request>,
+    monitor: VecDeque<Request>,
+}
+
+impl Queues {
+    fn pop(&mut self) -> Option<Request> {
+        self.emergency
+            .pop_front()
+            .or_else(|| self.control.pop_front())
+            .or_else(|| self.monitor.pop_front())
+    }
+}
+
+/// State of the link to the MRC-1, the commands fail at once unless connected
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum LinkState {
+    Connected,
+    Disconnected {
+        error: String,
+    },
+    /// the port is opened again after "retry_in_ms"
+    Reconnecting {
+        attempt: u32,
+        retry_in_ms: u64,
+        error: String,
+    },
+}
+
+/// How the actor opens the port again when the link is lost
+pub struct Reconnect {
+    pub open: Box<dyn FnMut() -> Result<Box<dyn Transport>, TransportError> + Send>,
+    /// the first pause, doubled at every attempt up to "max_delay"
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// consecutive timeouts before the link is considered lost, ex. power-cycled MRC-1
+    pub max_timeouts: u32,
+}
+
+// upper bounds of the latency histogram
+pub const LATENCY_BUCKETS: [f64; 9] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// Health counters of the serial link
+#[derive(Debug, Clone, Default)]
+pub struct SerialStats {
+    pub commands: u64,
+    pub parse_errors: u64,
+    pub timeouts: u64,
+    pub io_errors: u64,
+    pub reconnects: u64,
+    // cumulative like the Prometheus buckets
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    pub latency_sum: f64,
+}
+
+impl SerialStats {
+    fn add(&mut self, result: &Result<Response, LinkError>, latency: Duration) {
+        self.commands += 1;
+        match result {
+            Ok(_) => {}
+            Err(LinkError::Protocol(_)) => self.parse_errors += 1,
+            Err(LinkError::Transport(TransportError::Timeout(_))) => self.timeouts += 1,
+            Err(_) => self.io_errors += 1,
+        }
+
+        let seconds = latency.as_secs_f64();
+        self.latency_sum += seconds;
+        for (count, &le) in self.latency_buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= le {
+                *count += 1;
+            }
+        }
+    }
+}
+
+/// Handle to the serial I/O actor of one MRC-1 controller, the only owner of its port
+#[derive(Clone)]
+pub struct SerialHandle {
+    name: String,
+    queues: Arc<(Mutex<Queues>, Condvar)>,
+    stats: Arc<Mutex<SerialStats>>,
+    link: watch::Receiver<LinkState>,
+}
+
+impl SerialHandle {
+    /// the port is opened again by "reconnect" if it could not be opened at first
+    pub fn spawn(
+        name: &str,
+        port: Result<Box<dyn Transport>, TransportError>,
+        reconnect: Reconnect,
+        reply_timeout: Duration,
+        max_reply_size: usize,
+    ) -> SerialHandle {
+        let queues = Arc::new((Mutex::new(Queues::default()), Condvar::new()));
+        let stats = Arc::new(Mutex::new(SerialStats::default()));
+        let (link_tx, link) = watch::channel(match port {
+            Ok(_) => LinkState::Connected,
+            Err(ref e) => LinkState::Disconnected {
+                error: e.to_string(),
+            },
+        });
+        let actor = Actor {
+            name: name.to_string(),
+            queues: queues.clone(),
+            stats: stats.clone(),
+            link: link_tx,
+            reconnect,
+            reply_timeout,
+            max_reply_size,
+        };
+        // blocking I/O, so it runs on its own thread outside of the tokio runtime
+        thread::spawn(move || actor.run(port));
+        SerialHandle {
+            name: name.to_string(),
+            queues,
+            stats,
+            link,
+        }
+    }
+
+    /// the name of the controller, ex. "target"
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn link(&self) -> LinkState {
+        self.link.borrow().clone()
+    }
+
+    /// notified at every change of the link state
+    pub fn subscribe_link(&self) -> watch::Receiver<LinkState> {
+        self.link.clone()
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub async fn request(
+        &self,
+        command: Command,
+        priority: Priority,
+    ) -> Result<Response, LinkError> {
+        if *self.link.borrow() != LinkState::Connected {
+            return Err(LinkError::Disconnected);
+        }
+        let (tx, rx) = oneshot::channel();
+        {
+            let (lock, cvar) = &*self.queues;
+            let mut queues = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            let request = Request { command, reply: tx };
+            match priority {
+                Priority::Emergency => queues.emergency.push_back(request),
+                Priority::Control => queues.control.push_back(request),
+                Priority::Monitor => queues.monitor.push_back(request),
+            }
+            cvar.notify_one();
+        }
+        rx.await.map_err(|_| LinkError::Closed)?
+    }
+}
+
+// the serial actor, the only owner of the port
+struct Actor {
+    name: String,
+    queues: Arc<(Mutex<Queues>, Condvar)>,
+    stats: Arc<Mutex<SerialStats>>,
+    link: watch::Sender<LinkState>,
+    reconnect: Reconnect,
+    reply_timeout: Duration,
+    max_reply_size: usize,
+}
+
+impl Actor {
+    fn run(mut self, port: Result<Box<dyn Transport>, TransportError>) {
+        let mut timeouts: u32 = 0;
+        let mut delay = self.reconnect.min_delay;
+        let mut port = match port {
+            Ok(port) => port,
+            Err(e) => self.reopen(e.to_string(), &mut delay),
+        };
+        loop {
+            let request = {
+                let (lock, cvar) = &*self.queues;
+                let mut queues = lock.lock().unwrap_or_else(PoisonError::into_inner);
+                loop {
+                    if let Some(request) = queues.pop() {
+                        break request;
+                    }
+                    queues = cvar.wait(queues).unwrap_or_else(PoisonError::into_inner);
+                }
+            };
+
+            let started = Instant::now();
+            let result = exchange(
+                port.as_mut(),
+                &request.command,
+                self.reply_timeout,
+                self.max_reply_size,
+            );
+            self.stats
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .add(&result, started.elapsed());
+
+            let lost = match result {
+                Ok(_) => {
+                    timeouts = 0;
+                    delay = self.reconnect.min_delay;
+                    None
+                }
+                Err(LinkError::Transport(TransportError::Timeout(_))) => {
+                    timeouts += 1;
+                    (timeouts >= self.reconnect.max_timeouts)
+                        .then(|| format!("{} replies did not arrive in time", timeouts))
+                }
+                // something replied, so the link itself is fine
+                Err(ref e) if e.is_bad_reply() => {
+                    timeouts = 0;
+                    None
+                }
+                Err(ref e) => Some(e.to_string()),
+            };
+            // the requester may be gone, the command was sent anyway
+            let _ = request.reply.send(result);
+
+            if let Some(error) = lost {
+                timeouts = 0;
+                port = self.reopen(error, &mut delay);
+            }
+        }
+    }
+
+    // fails the waiting commands until the port is opened again
+    fn reopen(&mut self, mut error: String, delay: &mut Duration) -> Box<dyn Transport> {
+        log::error!("serial link of {} is lost: {}", self.name, error);
+        self.link.send_replace(LinkState::Disconnected {
+            error: error.clone(),
+        });
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            log::warn!(
+                "reopening the serial port of {} in {} ms (attempt {})",
+                self.name,
+                delay.as_millis(),
+                attempt
+            );
+            self.link.send_replace(LinkState::Reconnecting {
+                attempt,
+                retry_in_ms: delay.as_millis() as u64,
+                error: error.clone(),
+            });
+            self.fail_until(Instant::now() + *delay);
+            // not reset before a command succeeds, the port may open but not reply
+            *delay = (*delay * 2).min(self.reconnect.max_delay);
+
+            match (self.reconnect.open)() {
+                Ok(port) => {
+                    log::info!("{} is reconnected to {}", self.name, port.description());
+                    self.stats
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .reconnects += 1;
+                    self.link.send_replace(LinkState::Connected);
+                    return port;
+                }
+                Err(e) => {
+                    log::warn!("could not reopen the serial port of {}: {}", self.name, e);
+                    error = e.to_string();
+                }
+            }
+        }
+    }
+
+    // nothing is kept for later, the requesters get the error at once
+    fn fail_until(&self, deadline: Instant) {
+        let (lock, cvar) = &*self.queues;
+        let mut queues = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            while let Some(request) = queues.pop() {
+                log::warn!(
+                    "{:?} is not sent, the link of {} is down",
+                    request.command.to_wire().trim_end(),
+                    self.name
+                );
+                let _ = request.reply.send(Err(LinkError::Disconnected));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            queues = cvar
+                .wait_timeout(queues, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+fn exchange(
+    port: &mut dyn Transport,
+    command: &Command,
+    reply_timeout: Duration,
+    max_reply_size: usize,
+) -> Result<Response, LinkError> {
+    port.clear_input()?;
+    port.write_all(command.to_wire().as_bytes())?;
+    let string = transport::read_until_prompt(port, reply_timeout, max_reply_size)?;
+    log::trace!("result: {:?}", string);
+
+    Ok(protocol::parse_response(command, &string)?)
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    Transport(TransportError),
+    Protocol(ProtocolError),
+    Io(std::io::Error),
+    /// the link is lost, the command was not sent
+    Disconnected,
+    /// the actor is gone
+    Closed,
+}
+
+impl LinkError {
+    /// the reply was broken or did not arrive in time, the link itself may be fine
+    pub fn is_bad_reply(&self) -> bool {
+        matches!(
+            self,
+            LinkError::Protocol(_)
+                | LinkError::Transport(TransportError::Timeout(_))
+                | LinkError::Transport(TransportError::ReplyTooLong(_))
+        )
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::Transport(ref err) => write!(f, "TransportError: {}", err),
+            LinkError::Protocol(ref err) => write!(f, "ProtocolError: {}", err),
+            LinkError::Io(ref err) => write!(f, "Port I/O Error: {}", err),
+            LinkError::Disconnected => write!(f, "Serial link is lost, the command was not sent"),
+            LinkError::Closed => write!(f, "Serial actor is stopped"),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+impl From<TransportError> for LinkError {
+    fn from(err: TransportError) -> Self {
+        LinkError::Transport(err)
+    }
+}
+
+impl From<ProtocolError> for LinkError {
+    fn from(err: ProtocolError) -> Self {
+        LinkError::Protocol(err)
+    }
+}
+
+impl From<std::io::Error> for LinkError {
+    fn from(err: std::io::Error) -> Self {
+        LinkError::Io(err)
+    }
+}
@@ -1,149 +1,259 @@
+mod api;
+mod auth;
+mod metrics;
 mod mhv4;
+mod ramp;
+mod serial;
 mod shared;
+mod ws;
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use futures::{Stream, StreamExt};
 use mhv4::MHV4Data;
-use serialport::SerialPort;
-use shared::{CLArguments, OperationError, SharedData};
-use std::io::{Read, Write};
+use mhv4_monitor::audit::{self, AuditEntry, AuditFilter, AuditLog, AuditWriter};
+use mhv4_monitor::channel::ChannelId;
+use mhv4_monitor::config::{ChannelConfig, Config, ScannedChannel};
+use mhv4_monitor::credentials::{Credentials, Role};
+use mhv4_monitor::history::{self, Downsampling};
+use mhv4_monitor::limits::VoltageLimits;
+use mhv4_monitor::now_ms;
+use mhv4_monitor::protocol::{
+    Command, Response, BUS_NUM, CH_NUM, REG_CURRENT, REG_CURRENT_LIMIT, REG_HV_RANGE, REG_ONOFF,
+    REG_POLARITY, REG_RAMP_SPEED, REG_READBACK, REG_SETPOINT, REG_STATUS,
+};
+use mhv4_monitor::recorder::{Record, Recorder, RecorderConfig};
+use mhv4_monitor::transport::{self, TransportError};
+use mhv4_monitor::trip::{TripAction, TripDetector, TripEvent, TripKind};
+use ramp::RampEngine;
+use serde::{Deserialize, Serialize};
+use serial::{LinkState, Priority, Reconnect, SerialHandle, SERIAL};
+use shared::{
+    json_reply, CLArguments, ChannelReading, ChannelsEvent, MonitorValue, OperationError,
+    SharedData, StateEvent,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::result::Result;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 use std::thread;
+use tokio::signal::unix;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::time::{sleep, Duration};
 use warp::{sse::Event, Filter, Reply};
 
 static ARGS: OnceLock<CLArguments> = OnceLock::new();
-static PORT: OnceLock<Arc<Mutex<Box<dyn SerialPort>>>> = OnceLock::new();
+// the serial actor of every controller, in the order of the arguments
 static DATA: OnceLock<Arc<Mutex<SharedData>>> = OnceLock::new();
+static RAMP: OnceLock<RampEngine> = OnceLock::new();
+static MONITOR: OnceLock<broadcast::Sender<MonitorValue>> = OnceLock::new();
+// the config of the scanned channels, matched again at every rescan
+static CONFIG: Mutex<Vec<Option<ChannelConfig>>> = Mutex::new(Vec::new());
+static CONFIG_FILE: OnceLock<Config> = OnceLock::new();
+// the per-channel arguments are in the order of the first scan
+static FIRST_SCAN: OnceLock<Vec<ChannelId>> = OnceLock::new();
+// the controllers found as configured at least once
+static INITIALIZED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static LIMITS: OnceLock<Mutex<VoltageLimits>> = OnceLock::new();
+static TRIP: OnceLock<Mutex<TripDetector>> = OnceLock::new();
+static TRIP_EVENTS: OnceLock<broadcast::Sender<TripEvent>> = OnceLock::new();
+// None when the authentication is disabled
+static AUTH: OnceLock<Option<Credentials>> = OnceLock::new();
+static AUDIT: OnceLock<AuditWriter> = OnceLock::new();
+static STATE_EVENTS: OnceLock<broadcast::Sender<StateEvent>> = OnceLock::new();
+// the last state sent to the clients
+static LAST_STATE: Mutex<Option<StateEvent>> = Mutex::new(None);
+static CHANNEL_EVENTS: OnceLock<broadcast::Sender<ChannelsEvent>> = OnceLock::new();
 
-// when the server started, this function will be read
-async fn initialize_status() -> Result<(), OperationError> {
-    log::info!("Initializing...");
+// sent to the browser when the value could not be read
+const READ_ERROR_VALUE: isize = -100_000;
+const MAX_READ_RETRY: usize = 10;
+// deleting and downsampling the old records
+const RECORD_MAINTAIN_INTERVAL_MS: u64 = 60_000;
+// history of the last hour with 1000 points by default
+const HISTORY_RANGE_MS: u64 = 3_600_000;
+const HISTORY_POINTS: usize = 1000;
+const MAX_HISTORY_POINTS: usize = 10_000;
+// when the config does not have "current_limit"
+const DEFAULT_CURRENT_LIMIT: isize = 20000;
+// the last 100 audit entries by default
+const AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 10_000;
+// the user of the commands which are not requested over HTTP
+const SERVER_USER: &str = "server";
+
+// no channel until the first scan, the server runs without the hardware meanwhile
+fn initialize_status() -> Result<(), OperationError> {
+    let mut shared_data = SharedData::new(Vec::new(), false);
+    shared_data.unavailable = Some(String::from("Not initialized yet"));
+    for serial in SERIAL.get().into_iter().flatten() {
+        shared_data
+            .links
+            .insert(serial.name().to_string(), serial.link());
+    }
+    DATA.set(Arc::new(Mutex::new(shared_data)))
+        .map_err(|_| OperationError::OnceLockError)?;
+    Ok(())
+}
+
+// the first scan is retried with the same backoff as the serial link until every controller
+// is initialized, the ones already found are controlled meanwhile
+fn start_initializer() -> Result<(), OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    let (min_delay, max_delay) = (args.reconnect_min, args.reconnect_max);
+    tokio::spawn(async move {
+        let mut delay = min_delay;
+        loop {
+            log::info!("Initializing...");
+            let result = rescan().await;
+            let pending = pending_controllers();
+            if FIRST_SCAN.get().is_some() && pending.is_empty() {
+                log::info!("Initialization is completed!");
+                return;
+            }
+            let reason = match result {
+                Err(OperationError::Unavailable(reason)) => reason,
+                Err(e) => format!("Could not scan the modules: {}", e),
+                Ok(_) => format!("{} could not be initialized yet", pending.join(", ")),
+            };
+            log::error!("Initialization failed, retry in {} ms: {}", delay, reason);
+            match DATA.get().map(|x| x.lock()) {
+                Some(Ok(mut shared_data)) if FIRST_SCAN.get().is_none() => {
+                    shared_data.unavailable = Some(reason)
+                }
+                Some(Ok(_)) => (),
+                _ => log::error!("Error: {:?}", OperationError::SharedDataError),
+            }
+            if let Err(e) = notify_state() {
+                log::error!("Error: {:?}", e);
+            }
+            sleep(Duration::from_millis(delay)).await;
+            delay = (delay * 2).min(max_delay);
+        }
+    });
+    Ok(())
+}
+
+// the controllers which are not initialized yet, in the order of the arguments
+fn pending_controllers() -> Vec<String> {
+    let initialized = INITIALIZED.lock().unwrap_or_else(PoisonError::into_inner);
+    SERIAL
+        .get()
+        .into_iter()
+        .flatten()
+        .map(|x| x.name().to_string())
+        .filter(|x| !initialized.contains(x))
+        .collect()
+}
+
+// every controller in the order of the arguments, a controller which cannot be scanned
+// is marked down and keeps its channels of "previous", none before it is initialized
+async fn scan_modules(previous: &SharedData) -> Result<SharedData, OperationError> {
+    let mut mhv4_array: Vec<MHV4Data> = Vec::new();
+    let mut is_rc = false;
+    let mut down = BTreeMap::new();
+
+    for serial in SERIAL.get().ok_or(OperationError::PortGetError)? {
+        let name = serial.name();
+        match scan_controller(name).await {
+            Ok((channels, controller_rc)) => {
+                mhv4_array.extend(channels);
+                is_rc |= controller_rc;
+            }
+            Err(e) => {
+                log::warn!("{} could not be scanned: {}", name, e);
+                let kept = previous
+                    .get_data()
+                    .into_iter()
+                    .filter(|x| x.controller == name)
+                    .collect::<Vec<_>>();
+                if !kept.is_empty() {
+                    is_rc |= previous.is_rc;
+                }
+                mhv4_array.extend(kept);
+                down.insert(name.to_string(), e.to_string());
+            }
+        }
+    }
+    let mut shared_data = SharedData::new(mhv4_array, is_rc);
+    shared_data.down = down;
+    Ok(shared_data)
+}
+
+// "sc 0" and "sc 1", then the status of every channel of the MHV4 modules, with the RC mode
+async fn scan_controller(controller: &str) -> Result<(Vec<MHV4Data>, bool), OperationError> {
     let mut mhv4_array: Vec<MHV4Data> = Vec::new();
 
     // Check RC mode or not
     let mut is_rc = false;
     let mut is_first = true; // flag of first process or not
 
-    for bus in 0..2 {
+    for bus in 0..BUS_NUM {
         // scan command
-        let command = format!("sc {}\r", bus);
-        log::info!("command: {}", command);
-
-        let mut buf: Vec<u8> = vec![0; 300];
-        let size: usize;
+        let modules = match port_write_and_read(
+            controller,
+            Command::Scan { bus },
+            Priority::Monitor,
+        )
+        .await?
         {
-            let mut port = PORT.get().ok_or(OperationError::PortGetError)?.lock()?;
-            port.write(command.as_bytes())?;
-
-            std::thread::sleep(Duration::from_millis(100));
-            size = port.read(buf.as_mut_slice())?;
-        }
-        let bytes = &buf[..size];
-        let string = String::from_utf8(bytes.to_vec())?;
-        let modules = string.split("\n\r").collect::<Vec<_>>();
-        log::info!("result: {:?}", modules);
-
-        for dev in 0..16 {
-            let module = modules[dev + 2].to_string();
-            let datas = module.split_whitespace().collect::<Vec<_>>();
-            if datas[1] == "-" {
-                continue;
-            }
+            Response::Scan(modules) => modules,
+            _ => return Err(OperationError::DataGetError),
+        };
+        log::info!("result of {} bus {}: {:?}", controller, bus, modules);
 
-            let mut idc_str = datas[1].to_string();
-            idc_str.pop();
-            let idc: usize = idc_str.parse()?;
+        for module in modules {
+            let (dev, idc) = (module.dev, module.idc);
             if idc != 27 && idc != 17 {
                 log::debug!("find not MHV4 module, idc = {}", idc);
                 continue;
             }
 
-            if is_first {
-                let power_status = datas[2].to_string();
-                if power_status == "ON" {
-                    is_rc = true;
-                    is_first = false;
-                }
+            if is_first && module.is_on {
+                is_rc = true;
+                is_first = false;
             }
 
-            for ch in 0..4 {
+            for ch in 0..CH_NUM {
                 // read channel status ON/OFF
-                let mut is_on = false;
-                let command = format!("re {} {} {}\r", bus, dev, ch + 36);
-                let read_array = port_write_and_read(command)?;
-
-                if read_array.len() == 3 {
-                    let datas = read_array[1].split_whitespace().collect::<Vec<_>>();
-                    let status: usize = datas
-                        .last()
-                        .ok_or(OperationError::DataGetError)?
-                        .to_string()
-                        .parse()?;
-                    if status == 1 {
-                        is_on = true;
-                    }
-                } else {
-                    return Err(OperationError::DataGetError);
-                }
+                let is_on = read_register(controller, bus, dev, ch + REG_STATUS).await? == 1;
 
                 // read polarity
-                let mut is_positive = false;
-                let command = format!("re {} {} {}\r", bus, dev, ch + 46);
-                let read_array = port_write_and_read(command)?;
-
-                if read_array.len() == 3 {
-                    let datas = read_array[1].split_whitespace().collect::<Vec<_>>();
-                    let status: usize = datas
-                        .last()
-                        .ok_or(OperationError::DataGetError)?
-                        .to_string()
-                        .parse()?;
-                    if status == 1 {
-                        is_positive = true;
-                    }
-                } else {
-                    return Err(OperationError::DataGetError);
-                }
+                let is_positive =
+                    read_register(controller, bus, dev, ch + REG_POLARITY).await? == 1;
 
                 // read current HV
                 let mut tmp: isize = 10_000;
-                let current: isize;
+                let mut current: Option<isize> = None;
                 // sometimes read strange value, so check the stability using loop
-                loop {
-                    // read Voltage command
-                    let command = format!("re {} {} {}\r", bus, dev, ch + 32);
-                    let read_array = port_write_and_read(command)?;
-                    if read_array.len() != 3 {
-                        continue;
-                    } else {
-                        let datas = read_array[1].split_whitespace().collect::<Vec<_>>();
-                        let voltage: isize = datas
-                            .last()
-                            .ok_or(OperationError::DataGetError)?
-                            .to_string()
-                            .parse()?;
-                        if voltage != tmp {
-                            tmp = voltage;
-                            continue;
-                        } else if voltage.abs()
-                            > ARGS.get().ok_or(OperationError::ArgumentError)?.max_voltage
-                        {
-                            // check if it is over maximum voltage (for reading error)
+                for _ in 0..MAX_READ_RETRY {
+                    let voltage = match read_register(controller, bus, dev, ch + REG_READBACK).await
+                    {
+                        Ok(voltage) => voltage,
+                        Err(e) if e.is_bad_reply() => {
+                            log::debug!("retry reading the voltage: {}", e);
                             continue;
-                        } else {
-                            current = voltage.abs();
-                            break;
                         }
+                        Err(e) => return Err(e),
+                    };
+                    if voltage != tmp {
+                        tmp = voltage;
+                        continue;
+                    } else if voltage.abs()
+                        > ARGS.get().ok_or(OperationError::ArgumentError)?.max_voltage
+                    {
+                        // check if it is over maximum voltage (for reading error)
+                        continue;
+                    } else {
+                        current = Some(voltage.abs());
+                        break;
                     }
                 }
+                let current = current.ok_or(OperationError::DataGetError)?;
 
                 mhv4_array.push(MHV4Data::new(
+                    ChannelId::new(controller, bus, dev, ch),
                     idc,
-                    bus,
-                    dev,
-                    ch,
                     current,
                     is_on,
                     is_positive,
@@ -151,17 +261,300 @@ async fn initialize_status() -> Result<(), OperationError> {
             }
         }
     }
-    //let shared_data = Arc::new(Mutex::new(SharedData::new(mhv4_array.clone(), is_rc)));
-    let shared_data = Arc::new(Mutex::new(SharedData::new(mhv4_array, is_rc)));
-    DATA.set(shared_data)
+    Ok((mhv4_array, is_rc))
+}
+
+fn channel_ids(mhv4_data_array: &[MHV4Data]) -> Vec<ChannelId> {
+    mhv4_data_array.iter().map(|x| x.channel_id()).collect()
+}
+
+// checked against the modules at the first scan
+fn load_config() -> Result<(), OperationError> {
+    let config = match ARGS.get().ok_or(OperationError::ArgumentError)?.config {
+        Some(ref path) => {
+            log::info!("loading the channel config {}...", path);
+            Config::load(path)?
+        }
+        None => Config::default(),
+    };
+    CONFIG_FILE
+        .set(config)
         .map_err(|_| OperationError::OnceLockError)?;
+    Ok(())
+}
+
+fn scanned_channels(mhv4_data_array: &[MHV4Data]) -> Vec<ScannedChannel> {
+    mhv4_data_array
+        .iter()
+        .map(|x| ScannedChannel {
+            id: x.channel_id(),
+            is_positive: x.is_positive,
+        })
+        .collect()
+}
+
+// the config of every channel in the order of the scan
+fn match_config(
+    mhv4_data_array: &[MHV4Data],
+) -> Result<Vec<Option<ChannelConfig>>, OperationError> {
+    let config = CONFIG_FILE.get().ok_or(OperationError::ArgumentError)?;
+    Ok(mhv4_data_array
+        .iter()
+        .map(|x| {
+            let id = x.channel_id();
+            let channel = config.find(&id).cloned();
+            if channel.is_none() && !config.channels.is_empty() {
+                log::warn!("{} is not in the config", id);
+            }
+            channel
+        })
+        .collect())
+}
 
-    log::info!("Initialization is completed!");
+// the users who can access the server, if the file is given
+fn load_credentials() -> Result<(), OperationError> {
+    let credentials = match ARGS.get().ok_or(OperationError::ArgumentError)?.auth_file {
+        Some(ref path) => {
+            log::info!("loading the credentials {}...", path);
+            warn_if_readable(path);
+            let credentials = Credentials::load(path)?;
+            log::info!("{} users are registered", credentials.users.len());
+            Some(credentials)
+        }
+        None => {
+            log::warn!("authentication is disabled, anyone can control the modules");
+            None
+        }
+    };
+    AUTH.set(credentials)
+        .map_err(|_| OperationError::OnceLockError)?;
+    Ok(())
+}
+
+// the tokens should not be seen by the other users
+fn warn_if_readable(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o044 != 0 {
+            log::warn!(
+                "{} is readable by the other users, chmod 600 is recommended",
+                path
+            );
+        }
+    }
+}
+
+fn start_audit_log() -> Result<(), OperationError> {
+    let path = &ARGS.get().ok_or(OperationError::ArgumentError)?.audit_file;
+    log::info!("writing the control actions to {}", path);
+    AUDIT
+        .set(AuditWriter::start(AuditLog::open(path)?)?)
+        .map_err(|_| OperationError::OnceLockError)?;
     Ok(())
 }
 
+// the failure of the audit log does not stop the control
+fn audit<T: Serialize>(
+    user: &str,
+    address: Option<SocketAddr>,
+    action: &str,
+    payload: &T,
+    previous: serde_json::Value,
+    outcome: String,
+) {
+    let entry = AuditEntry {
+        time_ms: now_ms(),
+        user: user.to_string(),
+        address: address.map(|x| x.to_string()),
+        action: action.to_string(),
+        payload: serde_json::to_value(payload).unwrap_or_default(),
+        previous,
+        outcome,
+    };
+    if let Some(audit_log) = AUDIT.get() {
+        audit_log.send(entry);
+    }
+}
+
+fn outcome<T: Serialize>(result: &Result<T, OperationError>) -> String {
+    match result {
+        Ok(value) => serde_json::to_string(value).unwrap_or_default(),
+        Err(e) => e.to_string(),
+    }
+}
+
+// the state of all the channels before a control action
+fn state_snapshot() -> serde_json::Value {
+    match DATA.get().map(|x| x.lock()) {
+        Some(Ok(shared_data)) => serde_json::to_value(shared_data.state()).unwrap_or_default(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+/// the state changed by the command, None if the command does not change the modules
+fn command_state(controller: &str, command: &Command) -> Option<serde_json::Value> {
+    let shared_data = DATA.get()?.lock().ok()?;
+    match *command {
+        Command::Set { bus, dev, reg, .. } => {
+            let channel = |ch: usize| {
+                shared_data
+                    .get_data()
+                    .into_iter()
+                    .find(|x| x.controller == controller && x.get_module_id() == (bus, dev, ch))
+            };
+            let state = match reg {
+                r if (REG_SETPOINT..REG_SETPOINT + CH_NUM).contains(&r) => {
+                    channel(r - REG_SETPOINT)
+                        .map(|x| serde_json::json!({ "setpoint": x.get_current() }))
+                }
+                r if (REG_ONOFF..REG_ONOFF + CH_NUM).contains(&r) => {
+                    channel(r - REG_ONOFF).map(|x| serde_json::json!({ "is_on": x.is_on }))
+                }
+                _ => None,
+            };
+            Some(state.unwrap_or_default())
+        }
+        Command::On { .. } | Command::Off { .. } => {
+            Some(serde_json::json!({ "is_rc": shared_data.is_rc }))
+        }
+        _ => None,
+    }
+}
+
+/// every se, on and off sent to the modules
+fn audit_command(
+    controller: &str,
+    command: &Command,
+    previous: serde_json::Value,
+    result: &Result<Response, OperationError>,
+) {
+    let wire = command.to_string();
+    let action = wire.split_whitespace().next().unwrap_or_default();
+    let outcome = match result {
+        Ok(_) => String::from("ok"),
+        Err(e) => e.to_string(),
+    };
+    let payload = serde_json::json!({ "controller": controller, "command": wire });
+    audit(SERVER_USER, None, action, &payload, previous, outcome);
+}
+
+// every command of the server to the modules, the serial actors only queue them
+async fn port_write_and_read(
+    controller_name: &str,
+    command: Command,
+    priority: Priority,
+) -> Result<Response, OperationError> {
+    // se, on and off are written to the audit log
+    let previous = command_state(controller_name, &command);
+    let result = serial::controller(controller_name)?
+        .request(command, priority)
+        .await
+        .map_err(OperationError::from);
+    if let Some(previous) = previous {
+        audit_command(controller_name, &command, previous, &result);
+    }
+    result
+}
+
+async fn read_register(
+    controller: &str,
+    bus: usize,
+    dev: usize,
+    reg: usize,
+) -> Result<isize, OperationError> {
+    port_write_and_read(
+        controller,
+        Command::Read { bus, dev, reg },
+        Priority::Monitor,
+    )
+    .await?
+    .value()
+    .ok_or(OperationError::DataGetError)
+}
+
+async fn set_register(
+    controller: &str,
+    bus: usize,
+    dev: usize,
+    reg: usize,
+    value: isize,
+) -> Result<(), OperationError> {
+    set_register_with(controller, bus, dev, reg, value, Priority::Control).await
+}
+
+async fn set_register_with(
+    controller: &str,
+    bus: usize,
+    dev: usize,
+    reg: usize,
+    value: isize,
+    priority: Priority,
+) -> Result<(), OperationError> {
+    port_write_and_read(
+        controller,
+        Command::Set {
+            bus,
+            dev,
+            reg,
+            value,
+        },
+        priority,
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct AuditQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    user: Option<String>,
+    action: Option<String>,
+    limit: Option<usize>,
+}
+
+// the latest entries of the audit log
+async fn get_audit(
+    _user: String,
+    query: AuditQuery,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let limit = query.limit.unwrap_or(AUDIT_LIMIT);
+    if !(1..=MAX_AUDIT_LIMIT).contains(&limit) {
+        return Ok(json_reply::<()>(Err(OperationError::InvalidRequest(
+            format!("\"limit\" should be from 1 to {}", MAX_AUDIT_LIMIT),
+        ))));
+    }
+    let filter = AuditFilter {
+        from_ms: query.from,
+        to_ms: query.to,
+        user: query.user,
+        action: query.action,
+    };
+    let path = ARGS
+        .get()
+        .ok_or(OperationError::ArgumentError)?
+        .audit_file
+        .clone();
+
+    let result = tokio::task::spawn_blocking(move || audit::read(path, &filter, limit))
+        .await
+        .map_err(|_| warp::reject::custom(OperationError::DataGetError))?;
+    match result {
+        Ok(entries) => Ok(warp::reply::json(&entries).into_response()),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            Err(warp::reject::custom(OperationError::PortIOError))
+        }
+    }
+}
+
+// the config of a channel, if any
+fn channel_config(index: usize) -> Option<ChannelConfig> {
+    CONFIG.lock().ok()?.get(index)?.clone()
+}
+
 // when the page is loaded, this function will be read.
-async fn get_mhv4_data() -> Result<impl warp::Reply, warp::Rejection> {
+async fn get_mhv4_data(_user: String) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("getting current MHV4 status...");
     let shared_data = DATA
         .get()
@@ -177,24 +570,168 @@ async fn get_mhv4_data() -> Result<impl warp::Reply, warp::Rejection> {
     Ok(warp::reply::json(&data_json).into_response())
 }
 
-// SSE endpoint
+// channel configs in the order of "/mhv4_data", null when not configured
+async fn get_config(_user: String) -> Result<impl warp::Reply, warp::Rejection> {
+    let config = CONFIG
+        .lock()
+        .map_err(|_| warp::reject::custom(OperationError::MutexPoisonError))?
+        .clone();
+    Ok(warp::reply::json(&config))
+}
+
+// "/history?from=<ms>&to=<ms>&channels=target:0:0:0,focal:0:1:3&points=1000&method=minmax"
+#[derive(Deserialize, Debug)]
+struct HistoryQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    channels: Option<String>,
+    points: Option<usize>,
+    method: Option<Downsampling>,
+}
+
+// recorded voltages and currents, downsampled by the server
+async fn get_history(
+    _user: String,
+    query: HistoryQuery,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let bad_request = |message: String| {
+        Ok(json_reply::<()>(Err(OperationError::InvalidRequest(
+            message,
+        ))))
+    };
+
+    let dir = match ARGS.get().ok_or(OperationError::ArgumentError)?.record_dir {
+        Some(ref dir) => dir.clone(),
+        None => return bad_request(String::from("Recording is disabled")),
+    };
+    let to_ms = query.to.unwrap_or_else(now_ms);
+    let from_ms = query
+        .from
+        .unwrap_or_else(|| to_ms.saturating_sub(HISTORY_RANGE_MS));
+    if from_ms > to_ms {
+        return bad_request(String::from("\"from\" should be before \"to\""));
+    }
+    let points = query.points.unwrap_or(HISTORY_POINTS);
+    if !(3..=MAX_HISTORY_POINTS).contains(&points) {
+        return bad_request(format!(
+            "\"points\" should be from 3 to {}",
+            MAX_HISTORY_POINTS
+        ));
+    }
+    let channels = match query.channels {
+        Some(ref channels) => match parse_channels(channels) {
+            Some(channels) => channels,
+            None => {
+                return bad_request(format!(
+                    "Invalid channels \"{}\", use CONTROLLER:BUS:DEV:CH",
+                    channels
+                ))
+            }
+        },
+        None => Vec::new(),
+    };
+    let method = query.method.unwrap_or_default();
+
+    // reading the files blocks
+    let result = tokio::task::spawn_blocking(move || {
+        history::query(dir, &channels, from_ms, to_ms, points, method)
+    })
+    .await
+    .map_err(|_| warp::reject::custom(OperationError::DataGetError))?;
+    match result {
+        Ok(histories) => Ok(warp::reply::json(&histories).into_response()),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            Err(warp::reject::custom(OperationError::PortIOError))
+        }
+    }
+}
+
+// "target:0:0:0,focal:0:1:3", "0:0:0" is of the "default" controller
+fn parse_channels(s: &str) -> Option<Vec<ChannelId>> {
+    s.split(',').map(|id| id.trim().parse().ok()).collect()
+}
+
+// current ramp state with the per-channel progress
+async fn get_ramp_status(_user: String) -> Result<impl warp::Reply, warp::Rejection> {
+    let ramp = RAMP.get().ok_or(OperationError::SharedDataError)?;
+    Ok(warp::reply::json(&ramp.status()))
+}
+
+// trip thresholds, tripped channels and the latest trip events
+async fn get_trip_status(_user: String) -> Result<impl warp::Reply, warp::Rejection> {
+    let trip = TRIP
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()
+        .map_err(|_| warp::reject::custom(OperationError::MutexPoisonError))?;
+    Ok(warp::reply::json(&trip.status()))
+}
+
+// SSE endpoint, only forwards the snapshots of the background poller
+// and the trip events as "trip" events
 fn get_sse_stream() -> impl Stream<Item = Result<Event, OperationError>> {
     log::debug!("SSE handler start...");
-    futures::stream::unfold((), |()| async move {
-        match read_monitor_value().await {
-            Ok(result) => {
-                let json = match serde_json::to_string(&result) {
-                    Ok(json) => json,
-                    Err(e) => return Some((Err(OperationError::JSONSerializeError(e)), ())),
-                };
-                let sse_data = warp::sse::Event::default().data(json);
-                sleep(Duration::from_millis(100)).await;
-                Some((Ok::<_, OperationError>(sse_data), ()))
+    // the current state first, ex. the hardware is unavailable
+    let first = DATA
+        .get()
+        .and_then(|x| x.lock().ok())
+        .and_then(|x| serde_json::to_string(&x.state()).ok())
+        .map(|json| Ok(Event::default().event("state").data(json)));
+    let monitor = broadcast_stream(MONITOR.get().map(|tx| tx.subscribe()), None);
+    let trip = broadcast_stream(TRIP_EVENTS.get().map(|tx| tx.subscribe()), Some("trip"));
+    let state = broadcast_stream(STATE_EVENTS.get().map(|tx| tx.subscribe()), Some("state"));
+    let channels = broadcast_stream(
+        CHANNEL_EVENTS.get().map(|tx| tx.subscribe()),
+        Some("channels"),
+    );
+    futures::stream::iter(first).chain(futures::stream::select(
+        monitor,
+        futures::stream::select(trip, futures::stream::select(state, channels)),
+    ))
+}
+
+// sends the state to the clients if it is changed since the last time
+fn notify_state() -> Result<(), OperationError> {
+    let state = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .state();
+    let mut last_state = LAST_STATE.lock()?;
+    if last_state.as_ref() != Some(&state) {
+        *last_state = Some(state.clone());
+        if let Some(tx) = STATE_EVENTS.get() {
+            // error only when no client is connected
+            let _ = tx.send(state);
+        }
+    }
+    Ok(())
+}
+
+fn broadcast_stream<T: Serialize + Clone + Send + 'static>(
+    rx: Option<broadcast::Receiver<T>>,
+    name: Option<&'static str>,
+) -> impl Stream<Item = Result<Event, OperationError>> {
+    futures::stream::unfold(rx, move |rx| async move {
+        let mut rx = rx?;
+        let result = loop {
+            match rx.recv().await {
+                Ok(result) => break result,
+                // slow client, skip to the latest snapshot
+                Err(RecvError::Lagged(num)) => log::debug!("SSE client skipped {} snapshots", num),
+                Err(RecvError::Closed) => return None,
             }
-            Err(e) => {
-                log::error!("Error in the sse stream: {:?}", e);
-                Some((Err(OperationError::ReadingError), ()))
+        };
+        match serde_json::to_string(&result) {
+            Ok(json) => {
+                let mut sse_data = warp::sse::Event::default().data(json);
+                if let Some(name) = name {
+                    sse_data = sse_data.event(name);
+                }
+                Some((Ok::<_, OperationError>(sse_data), Some(rx)))
             }
+            Err(e) => Some((Err(OperationError::JSONSerializeError(e)), Some(rx))),
         }
     })
     .filter_map(|result| async move {
@@ -205,7 +742,187 @@ fn get_sse_stream() -> impl Stream<Item = Result<Event, OperationError>> {
     })
 }
 
-async fn read_monitor_value() -> Result<(Vec<isize>, Vec<isize>, bool), OperationError> {
+// one acquisition loop for all the SSE clients
+fn start_poller() -> Result<(), OperationError> {
+    let (tx, _) = broadcast::channel::<MonitorValue>(16);
+    MONITOR
+        .set(tx.clone())
+        .map_err(|_| OperationError::OnceLockError)?;
+    let (state_tx, _) = broadcast::channel::<StateEvent>(16);
+    STATE_EVENTS
+        .set(state_tx)
+        .map_err(|_| OperationError::OnceLockError)?;
+    let (channels_tx, _) = broadcast::channel::<ChannelsEvent>(16);
+    CHANNEL_EVENTS
+        .set(channels_tx)
+        .map_err(|_| OperationError::OnceLockError)?;
+    let interval = ARGS
+        .get()
+        .ok_or(OperationError::ArgumentError)?
+        .poll_interval;
+
+    tokio::spawn(async move {
+        loop {
+            match read_monitor_value().await {
+                // the channels were rescanned during the reading
+                Ok(None) => (),
+                Ok(Some(result)) => {
+                    if let Err(e) = store_reading(&result) {
+                        log::error!("Error in the poller: {:?}", e);
+                    }
+                    if let Err(e) = check_trip(&result.currents()) {
+                        log::error!("Error in the trip detection: {:?}", e);
+                    }
+                    // ramp steps and trip actions
+                    if let Err(e) = notify_state() {
+                        log::error!("Error in the poller: {:?}", e);
+                    }
+                    // error only when no client is connected
+                    let _ = tx.send(result);
+                }
+                Err(e) => {
+                    // the actor reports the lost link
+                    if !matches!(e, OperationError::Disconnected) {
+                        log::error!("Error in the poller: {:?}", e);
+                    }
+                    // the clients see that nothing could be read
+                    match failed_monitor_value() {
+                        Ok(result) => {
+                            let _ = store_reading(&result);
+                            let _ = tx.send(result);
+                        }
+                        Err(e) => log::error!("Error in the poller: {:?}", e),
+                    }
+                }
+            }
+            sleep(Duration::from_millis(interval)).await;
+        }
+    });
+    Ok(())
+}
+
+fn failed_monitor_value() -> Result<MonitorValue, OperationError> {
+    let shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+    Ok(MonitorValue {
+        channels: shared_data
+            .get_data()
+            .iter()
+            .map(|x| ChannelReading {
+                id: x.channel_id(),
+                voltage: READ_ERROR_VALUE,
+                current: READ_ERROR_VALUE,
+            })
+            .collect(),
+        is_progress: shared_data.is_progress,
+    })
+}
+
+// the clients are told about the serial links, and the modules are scanned again
+// when a link is back
+fn start_link_monitor() -> Result<(), OperationError> {
+    for serial in SERIAL.get().ok_or(OperationError::PortGetError)? {
+        let name = serial.name().to_string();
+        let mut rx = serial.subscribe_link();
+        tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let link = rx.borrow_and_update().clone();
+                match DATA.get().map(|x| x.lock()) {
+                    Some(Ok(mut shared_data)) => {
+                        shared_data.links.insert(name.clone(), link.clone());
+                    }
+                    _ => continue,
+                }
+                if let Err(e) = notify_state() {
+                    log::error!("Error: {:?}", e);
+                }
+                // the modules may be power-cycled or replaced meanwhile,
+                // before the first scan the initializer retries by itself
+                if link == LinkState::Connected && FIRST_SCAN.get().is_some() {
+                    if let Err(e) = control(SERVER_USER, None, ControlRequest::Rescan).await {
+                        log::error!("Error in the rescan after the reconnection: {:?}", e);
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+fn store_reading(result: &MonitorValue) -> Result<(), OperationError> {
+    let mut shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+    shared_data.voltages = result.voltages();
+    shared_data.currents = result.currents();
+    Ok(())
+}
+
+// records every snapshot of the poller, whether a browser is connected or not
+fn start_recorder() -> Result<(), OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    let dir = match args.record_dir {
+        Some(ref dir) => PathBuf::from(dir),
+        None => return Ok(()),
+    };
+    let mut recorder = Recorder::new(RecorderConfig {
+        dir,
+        rotate_ms: args.record_rotate * 60 * 1000,
+        retention_ms: args.record_retention * 24 * 3600 * 1000,
+        downsample_after_ms: args.record_downsample_after * 3600 * 1000,
+        bucket_ms: args.record_bucket * 1000,
+    })?;
+    let mut rx = MONITOR
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .subscribe();
+
+    // file I/O, so it runs on its own thread like the serial actor
+    thread::spawn(move || {
+        let mut last_maintain: u64 = 0;
+        loop {
+            let result = match rx.blocking_recv() {
+                Ok(result) => result,
+                Err(RecvError::Lagged(num)) => {
+                    log::warn!("recorder skipped {} snapshots", num);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let time_ms = now_ms();
+            let channels = result
+                .channels
+                .iter()
+                .map(|x| x.id.clone())
+                .collect::<Vec<_>>();
+            let to_option = |x: isize| Some(x).filter(|&x| x != READ_ERROR_VALUE);
+            let record = Record {
+                time_ms,
+                voltages: result
+                    .channels
+                    .iter()
+                    .map(|x| to_option(x.voltage))
+                    .collect(),
+                currents: result
+                    .channels
+                    .iter()
+                    .map(|x| to_option(x.current))
+                    .collect(),
+            };
+            if let Err(e) = recorder.record(&channels, &record) {
+                log::error!("Error in the recorder: {:?}", e);
+            }
+
+            if time_ms.saturating_sub(last_maintain) > RECORD_MAINTAIN_INTERVAL_MS {
+                last_maintain = time_ms;
+                if let Err(e) = recorder.maintain(time_ms) {
+                    log::error!("Error in the record maintenance: {:?}", e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+// None if the channels are changed by a rescan during the reading
+async fn read_monitor_value() -> Result<Option<MonitorValue>, OperationError> {
     let mhv4_data_array: Vec<MHV4Data>;
     let is_progress: bool;
     {
@@ -214,59 +931,247 @@ async fn read_monitor_value() -> Result<(Vec<isize>, Vec<isize>, bool), Operatio
         is_progress = shared_data.is_progress;
     }
 
-    let mut v_array: Vec<isize> = Vec::new();
-    let mut c_array: Vec<isize> = Vec::new();
+    let mut readings: Vec<ChannelReading> = Vec::new();
 
-    for i in 0..mhv4_data_array.len() {
-        let (bus, dev, ch) = mhv4_data_array[i].get_module_id();
+    for mhv4_data in mhv4_data_array.iter() {
+        let (bus, dev, ch) = mhv4_data.get_module_id();
+        let controller = &mhv4_data.controller;
 
         // read HV value
-        let command = format!("re {} {} {}\r", bus, dev, ch + 32);
-        let read_array = port_write_and_read(command)?;
-        if read_array.len() > 1 {
-            let datas = read_array[1].split_whitespace().collect::<Vec<_>>();
-            let voltage = match datas.last() {
-                Some(str) => str.to_string(),
-                None => String::from("-100_000"),
-            };
-            match voltage.parse() {
-                Ok(num) => v_array.push(num),
-                Err(_) => {
-                    log::error!("SSE read error: {:?}", read_array);
-                    v_array.push(-100_000);
-                }
+        let voltage = match read_register(controller, bus, dev, ch + REG_READBACK).await {
+            Ok(voltage) => voltage,
+            Err(e) if e.is_bad_reply() => {
+                log::error!("SSE read error: {}", e);
+                READ_ERROR_VALUE
             }
-        } else {
-            log::error!("SSE read error: {:?}", read_array);
-            v_array.push(-100_000);
-        }
+            // the other controllers are still read, the actor reports the lost link
+            Err(OperationError::Disconnected) => READ_ERROR_VALUE,
+            Err(e) => return Err(e),
+        };
 
         // read current value
-        let command = format!("re {} {} {}\r", bus, dev, ch + 50);
-        let read_array = port_write_and_read(command)?;
-        if read_array.len() > 1 {
-            let datas = read_array[1].split_whitespace().collect::<Vec<_>>();
-            let current = match datas.last() {
-                Some(str) => str.to_string(),
-                None => String::from("-100_000"),
-            };
-            match current.parse() {
-                Ok(num) => c_array.push(num),
-                Err(_) => {
-                    log::error!("SSE read error: {:?}", read_array);
-                    c_array.push(-100_000);
+        let current = match read_register(controller, bus, dev, ch + REG_CURRENT).await {
+            Ok(current) => current,
+            Err(e) if e.is_bad_reply() => {
+                log::error!("SSE read error: {}", e);
+                READ_ERROR_VALUE
+            }
+            Err(OperationError::Disconnected) => READ_ERROR_VALUE,
+            Err(e) => return Err(e),
+        };
+        readings.push(ChannelReading {
+            id: mhv4_data.channel_id(),
+            voltage,
+            current,
+        });
+    }
+
+    let channels = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .get_data();
+    if channel_ids(&channels) != channel_ids(&mhv4_data_array) {
+        return Ok(None);
+    }
+    Ok(Some(MonitorValue {
+        channels: readings,
+        is_progress,
+    }))
+}
+
+// one value for all the channels or one per channel in the order of the first scan,
+// 0 is None, and the channels found by a rescan have no value of a list
+fn channel_values(
+    list: &[isize],
+    name: &str,
+    channels: &[ChannelId],
+    first_scan: &[ChannelId],
+) -> Result<Vec<Option<isize>>, OperationError> {
+    // the list is checked at the first scan
+    if channels.is_empty() {
+        return Ok(Vec::new());
+    }
+    match list {
+        [] => Ok(vec![None; channels.len()]),
+        &[value] => Ok(vec![Some(value).filter(|&x| x > 0); channels.len()]),
+        list if list.len() == first_scan.len() => Ok(channels
+            .iter()
+            .map(|id| {
+                let value = first_scan.iter().position(|x| x == id).map(|i| list[i]);
+                if value.is_none() {
+                    log::warn!("{} has no value of {}", id, name);
                 }
+                value.filter(|&x| x > 0)
+            })
+            .collect()),
+        // the modules do not match the arguments
+        list => Err(OperationError::Unavailable(format!(
+            "{} values of {} are given, but {} channels are found",
+            list.len(),
+            name,
+            first_scan.len()
+        ))),
+    }
+}
+
+// the slew of the arguments is checked before any channel is found
+fn start_voltage_limits() -> Result<(), OperationError> {
+    let limits = voltage_limits(&[], &[], &[])?;
+    log::info!("voltage limits: {:?}", limits);
+    LIMITS
+        .set(Mutex::new(limits))
+        .map_err(|_| OperationError::OnceLockError)?;
+    Ok(())
+}
+
+// the ramp settings of the arguments should also be in the limits
+fn voltage_limits(
+    channels: &[ChannelId],
+    first_scan: &[ChannelId],
+    configs: &[Option<ChannelConfig>],
+) -> Result<VoltageLimits, OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    // the lower of the argument and the config
+    let channel_max = channel_values(
+        &args.channel_max_voltage,
+        "channel_max_voltage",
+        channels,
+        first_scan,
+    )?
+    .into_iter()
+    .zip(configs.iter())
+    .map(|(max, config)| {
+        let config_max = config.as_ref().and_then(|x| x.max_voltage);
+        match (max, config_max) {
+            (Some(max), Some(config_max)) => Some(max.min(config_max)),
+            (max, config_max) => max.or(config_max),
+        }
+    })
+    .collect();
+    let limits = VoltageLimits::new(args.max_voltage, channel_max, args.min_slew, args.max_slew);
+    limits.check_slew(args.voltage_step, args.waiting_time)?;
+    limits.check_slew(args.emergency_step, args.emergency_waiting_time)?;
+    Ok(limits)
+}
+
+fn start_trip_detector() -> Result<(), OperationError> {
+    TRIP.set(Mutex::new(trip_detector(&[], &[])?))
+        .map_err(|_| OperationError::OnceLockError)?;
+    let (tx, _) = broadcast::channel::<TripEvent>(16);
+    TRIP_EVENTS
+        .set(tx)
+        .map_err(|_| OperationError::OnceLockError)?;
+    Ok(())
+}
+
+fn trip_detector(
+    channels: &[ChannelId],
+    first_scan: &[ChannelId],
+) -> Result<TripDetector, OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    let thresholds = channel_values(&args.trip_current, "trip_current", channels, first_scan)?;
+    log::info!(
+        "trip thresholds: {:?}, action: {:?}",
+        thresholds,
+        args.trip_action
+    );
+
+    Ok(TripDetector::new(
+        thresholds,
+        args.trip_action,
+        args.trip_debounce,
+        args.trip_hysteresis,
+    ))
+}
+
+fn check_trip(currents: &[isize]) -> Result<(), OperationError> {
+    let currents = currents
+        .iter()
+        .map(|&x| Some(x).filter(|&x| x != READ_ERROR_VALUE))
+        .collect::<Vec<_>>();
+    let events = TRIP
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .check(&currents);
+
+    for event in events {
+        match event.kind {
+            TripKind::Tripped => log::warn!(
+                "channel {} tripped: {} > {}, action: {:?}",
+                event.index,
+                event.current.abs(),
+                event.threshold,
+                event.action
+            ),
+            TripKind::Released => {
+                log::info!("channel {} released: {}", event.index, event.current.abs())
+            }
+        }
+        if event.kind == TripKind::Tripped {
+            // the poller keeps reading during the action
+            let index = event.index;
+            let action = event.action;
+            tokio::spawn(async move {
+                if let Err(e) = run_trip_action(index, action).await {
+                    log::error!("Error: {:?}", e);
+                }
+            });
+        }
+        if let Some(tx) = TRIP_EVENTS.get() {
+            let _ = tx.send(event);
+        }
+    }
+    Ok(())
+}
+
+async fn run_trip_action(index: usize, action: TripAction) -> Result<(), OperationError> {
+    let ramp = RAMP.get().ok_or(OperationError::SharedDataError)?;
+    match action {
+        TripAction::Log => {}
+        TripAction::Hold => {
+            if ramp.pause() {
+                log::warn!("the ramp is paused by the trip of channel {}", index);
             }
-        } else {
-            log::error!("SSE read error: {:?}", read_array);
-            c_array.push(-100_000);
+        }
+        TripAction::RampDown => {
+            let safe_voltage = ARGS
+                .get()
+                .ok_or(OperationError::ArgumentError)?
+                .trip_safe_voltage;
+            ramp.ramp_down(index, safe_voltage).await?;
+        }
+        TripAction::Off => {
+            let mhv4_data = DATA
+                .get()
+                .ok_or(OperationError::SharedDataError)?
+                .lock()?
+                .get_data()
+                .get(index)
+                .cloned()
+                .ok_or(OperationError::DataGetError)?;
+            let (bus, dev, ch) = mhv4_data.get_module_id();
+            set_register_with(
+                &mhv4_data.controller,
+                bus,
+                dev,
+                ch + REG_ONOFF,
+                0,
+                Priority::Emergency,
+            )
+            .await?;
+            DATA.get()
+                .ok_or(OperationError::SharedDataError)?
+                .lock()?
+                .set_onoff(&mhv4_data.channel_id(), false);
         }
     }
-    Ok((v_array, c_array, is_progress))
+    Ok(())
 }
 
 // 0: RC on, 1: RC off
-fn set_rcstatus(do_rc: bool) -> Result<bool, OperationError> {
+async fn set_rcstatus(do_rc: bool) -> Result<bool, OperationError> {
     log::info!("set_rcstatus is called");
     let mhv4_data_array: Vec<MHV4Data>;
     let current_rc: bool;
@@ -274,30 +1179,34 @@ fn set_rcstatus(do_rc: bool) -> Result<bool, OperationError> {
         let shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
         mhv4_data_array = shared_data.get_data();
         current_rc = shared_data.is_rc;
+        // the ramp would lose the control of the modules
+        if shared_data.is_progress {
+            return Err(OperationError::RampInProgress);
+        }
     }
 
     // remote ON
     if do_rc && !current_rc {
         // if you use IDC=27 MHV4, please prepare polarity list
-        for i in 0..mhv4_data_array.len() {
-            let (bus, dev, ch) = mhv4_data_array[i].get_module_id();
-            let command = format!("on {} {}\r", bus, dev);
-            let _ = port_write_and_read(command)?;
+        for (i, mhv4_data) in mhv4_data_array.into_iter().enumerate() {
+            let (bus, dev, ch) = mhv4_data.get_module_id();
+            let controller = &mhv4_data.controller;
+            port_write_and_read(controller, Command::On { bus, dev }, Priority::Control).await?;
 
             // current limit
-            let command = format!("se {} {} {} 20000\r", bus, dev, ch + 8);
-            let _ = port_write_and_read(command)?;
+            let current_limit = channel_config(i)
+                .and_then(|x| x.current_limit)
+                .unwrap_or(DEFAULT_CURRENT_LIMIT);
+            set_register(controller, bus, dev, ch + REG_CURRENT_LIMIT, current_limit).await?;
 
             // if you use IDC=27 MHV4, you can set polarity or something in here
-            let idc = mhv4_data_array[i].idc;
+            let idc = mhv4_data.idc;
             if idc == 27 {
                 // ramp speed setting
-                let command = format!("se {} {} 80 0\r", bus, dev);
-                let _ = port_write_and_read(command)?;
+                set_register(controller, bus, dev, REG_RAMP_SPEED, 0).await?;
             } else {
                 // HV range setting
-                let command = format!("se {} {} 13 1\r", bus, dev);
-                let _ = port_write_and_read(command)?;
+                set_register(controller, bus, dev, REG_HV_RANGE, 1).await?;
             }
         }
 
@@ -307,10 +1216,14 @@ fn set_rcstatus(do_rc: bool) -> Result<bool, OperationError> {
         }
     // remote OFF
     } else if !do_rc && current_rc {
-        for i in 0..mhv4_data_array.len() {
-            let (bus, dev, _) = mhv4_data_array[i].get_module_id();
-            let command = format!("off {} {}\r", bus, dev);
-            let _ = port_write_and_read(command)?;
+        for mhv4_data in mhv4_data_array {
+            let (bus, dev, _) = mhv4_data.get_module_id();
+            port_write_and_read(
+                &mhv4_data.controller,
+                Command::Off { bus, dev },
+                Priority::Control,
+            )
+            .await?;
         }
 
         {
@@ -321,116 +1234,365 @@ fn set_rcstatus(do_rc: bool) -> Result<bool, OperationError> {
     Ok(true)
 }
 
-fn set_onoff(arr: Vec<bool>) -> Result<bool, OperationError> {
+async fn set_onoff(arr: Vec<bool>) -> Result<bool, OperationError> {
     log::info!("set_onoff is called");
     let mhv4_data_array: Vec<MHV4Data>;
     {
         let shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
         mhv4_data_array = shared_data.get_data();
+        if !shared_data.is_rc {
+            return Err(OperationError::RcOff);
+        }
+    }
+    if arr.len() != mhv4_data_array.len() {
+        return Err(OperationError::InvalidRequest(format!(
+            "{} ON/OFF values are expected, found {}",
+            mhv4_data_array.len(),
+            arr.len()
+        )));
     }
 
-    for i in 0..mhv4_data_array.len() {
-        let is_on = mhv4_data_array[i].is_on;
-        if is_on != arr[i] {
-            if arr[i] {
-                let (bus, dev, ch) = mhv4_data_array[i].get_module_id();
-                let command = format!("se {} {} {} 1\r", bus, dev, ch + 4);
-                let _ = port_write_and_read(command)?;
-            } else {
-                let (bus, dev, ch) = mhv4_data_array[i].get_module_id();
-                let command = format!("se {} {} {} 0\r", bus, dev, ch + 4);
-                let _ = port_write_and_read(command)?;
-            }
+    for (mhv4_data, &do_on) in mhv4_data_array.iter().zip(arr.iter()) {
+        if mhv4_data.is_on != do_on {
+            let (bus, dev, ch) = mhv4_data.get_module_id();
+            set_register(
+                &mhv4_data.controller,
+                bus,
+                dev,
+                ch + REG_ONOFF,
+                do_on as isize,
+            )
+            .await?;
         }
     }
 
     {
         let mut shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
-        for i in 0..arr.len() {
-            shared_data.set_onoff(i, arr[i]);
+        for (mhv4_data, &do_on) in mhv4_data_array.iter().zip(arr.iter()) {
+            shared_data.set_onoff(&mhv4_data.channel_id(), do_on);
         }
     }
     Ok(true)
 }
 
-fn set_voltage(nums: Vec<isize>) -> Result<bool, OperationError> {
-    let mhv4_data_array: Vec<MHV4Data>;
+// the voltages only, or with the ramp speed of this request
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum ApplyRequest {
+    Voltages(Vec<isize>),
+    Ramp {
+        voltages: Vec<isize>,
+        step: Option<isize>,
+        waiting_time_ms: Option<u64>,
+    },
+}
+
+async fn set_voltage(request: ApplyRequest) -> Result<bool, OperationError> {
+    let (nums, step, waiting_time) = check_voltage(&request)?;
+    let mhv4_data_array = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .get_data();
+    RAMP.get().ok_or(OperationError::SharedDataError)?.start(
+        mhv4_data_array,
+        nums,
+        step,
+        waiting_time,
+    )?;
+    Ok(true)
+}
+
+/// the setpoints, "step" and "waiting_time" of the request if it can be started now
+fn check_voltage(request: &ApplyRequest) -> Result<(Vec<isize>, isize, u64), OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    let (nums, step, waiting_time) = match *request {
+        ApplyRequest::Voltages(ref nums) => (nums.clone(), args.voltage_step, args.waiting_time),
+        ApplyRequest::Ramp {
+            ref voltages,
+            step,
+            waiting_time_ms,
+        } => (
+            voltages.clone(),
+            step.unwrap_or(args.voltage_step),
+            waiting_time_ms.unwrap_or(args.waiting_time),
+        ),
+    };
+
+    // nothing is sent when one of the values is out of the limits
+    let limits = LIMITS
+        .get()
+        .ok_or(OperationError::ArgumentError)?
+        .lock()?
+        .clone();
+    limits.check_setpoints(&nums)?;
+    limits.check_slew(step, waiting_time)?;
+
+    if !DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .is_rc
     {
-        let mut shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
-        shared_data.is_progress = true;
-        mhv4_data_array = shared_data.get_data();
+        return Err(OperationError::RcOff);
     }
+    let ramp = RAMP.get().ok_or(OperationError::SharedDataError)?.status();
+    if ramp.is_running() || ramp.is_emergency {
+        return Err(OperationError::RampInProgress);
+    }
+    Ok((nums, step, waiting_time))
+}
 
-    let mut voltage_now_array: Vec<isize> =
-        mhv4_data_array.iter().map(|x| x.get_current()).collect();
-    let mut count: usize = 0;
-    let mut is_finish: Vec<bool> = vec![false; mhv4_data_array.len()];
-    let step = ARGS.get().unwrap().voltage_step;
-    let waiting_time = ARGS.get().unwrap().waiting_time;
-
-    thread::spawn(move || {
-        loop {
-            for i in 0..mhv4_data_array.len() {
-                if voltage_now_array[i] == nums[i] {
-                    if !is_finish[i] {
-                        is_finish[i] = true;
-                        count += 1;
-                    }
-                    continue;
-                } else if (voltage_now_array[i] - nums[i]).abs() < step {
-                    voltage_now_array[i] = nums[i];
-                } else if voltage_now_array[i] < nums[i] {
-                    voltage_now_array[i] += step;
-                } else {
-                    voltage_now_array[i] -= step;
-                }
-                let (bus, dev, ch) = mhv4_data_array[i].get_module_id();
-                let command = format!("se {} {} {} {}\r", bus, dev, ch, voltage_now_array[i]);
-                let _ = port_write_and_read(command).expect("error in the thread");
-            }
-            if count == mhv4_data_array.len() {
-                break;
-            }
+/// A control action of a client, from the HTTP routes or the WebSocket
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "action", content = "value", rename_all = "lowercase")]
+pub enum ControlRequest {
+    Status(bool),
+    Onoff(Vec<bool>),
+    Apply(ApplyRequest),
+    Ramp(String),
+    Emergency,
+    Rescan,
+}
 
-            if waiting_time > (60 * (mhv4_data_array.len() - count)) as u64 {
-                std::thread::sleep(Duration::from_millis(
-                    waiting_time - 60 * ((mhv4_data_array.len() - count) as u64),
-                ));
-            }
+impl ControlRequest {
+    fn action(&self) -> &'static str {
+        match self {
+            ControlRequest::Status(_) => "status",
+            ControlRequest::Onoff(_) => "onoff",
+            ControlRequest::Apply(_) => "apply",
+            ControlRequest::Ramp(_) => "ramp",
+            ControlRequest::Emergency => "emergency",
+            ControlRequest::Rescan => "rescan",
         }
+    }
+}
 
-        {
-            let mut shared_data = DATA.get().unwrap().lock().unwrap();
-            shared_data.is_progress = false;
-            for i in 0..mhv4_data_array.len() {
-                shared_data.set_current(i, nums[i]);
-            }
+/// runs the action of the user and writes it to the audit log
+pub async fn control(
+    user: &str,
+    address: Option<SocketAddr>,
+    request: ControlRequest,
+) -> Result<bool, OperationError> {
+    let action = request.action();
+    log::info!("{} is requested by {}", action, user);
+    let previous = state_snapshot();
+    let payload = match serde_json::to_value(&request) {
+        Ok(serde_json::Value::Object(mut map)) => map.remove("value").unwrap_or_default(),
+        _ => serde_json::Value::Null,
+    };
+    let result = match request {
+        // the rescan also initializes the modules
+        ControlRequest::Rescan => rescan().await,
+        _ if FIRST_SCAN.get().is_none() => Err(OperationError::Unavailable(unavailable_reason())),
+        ControlRequest::Status(do_rc) => set_rcstatus(do_rc).await,
+        ControlRequest::Onoff(arr) => set_onoff(arr).await,
+        ControlRequest::Apply(request) => set_voltage(request).await,
+        ControlRequest::Ramp(operation) => control_ramp(&operation),
+        ControlRequest::Emergency => emergency_off().await,
+    };
+    audit(user, address, action, &payload, previous, outcome(&result));
+    // the other clients are told at once
+    if let Err(e) = notify_state() {
+        log::error!("Error: {:?}", e);
+    }
+    result
+}
+
+fn unavailable_reason() -> String {
+    DATA.get()
+        .and_then(|x| x.lock().ok())
+        .and_then(|x| x.unavailable.clone())
+        .unwrap_or_default()
+}
+
+// pause, resume or abort the running ramp
+fn control_ramp(operation: &str) -> Result<bool, OperationError> {
+    let ramp = RAMP.get().ok_or(OperationError::SharedDataError)?;
+    let is_done = match operation {
+        "pause" => ramp.pause(),
+        "resume" => ramp.resume(),
+        "abort" => ramp.abort(),
+        _ => {
+            return Err(OperationError::InvalidRequest(format!(
+                "Unknown ramp operation \"{}\", use pause, resume or abort",
+                operation
+            )))
         }
-    });
+    };
+    if !is_done {
+        return Err(OperationError::RampStateError(format!(
+            "Ramp cannot {} now",
+            operation
+        )));
+    }
     Ok(true)
 }
 
-fn port_write_and_read(command: String) -> Result<Vec<String>, OperationError> {
-    //log::trace!("command: {}", command);
+// scans the modules again, the config and the limits follow the new channels
+async fn rescan() -> Result<bool, OperationError> {
+    log::info!("Rescanning the modules...");
+    let old_data: SharedData;
+    {
+        let shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+        old_data = shared_data.clone();
+        // the ramp holds the indices of the old channels
+        if shared_data.is_progress {
+            return Err(OperationError::RampInProgress);
+        }
+    }
 
-    let mut buf: Vec<u8> = vec![0; 100];
-    let size: usize;
+    let old_channels = channel_ids(&old_data.get_data());
+    // a lost controller keeps its channels once it is initialized
+    let mut new_data = scan_modules(&old_data).await?;
+    let config = CONFIG_FILE.get().ok_or(OperationError::ArgumentError)?;
+    let initialized = INITIALIZED.lock()?.clone();
+    let serials = SERIAL.get().ok_or(OperationError::PortGetError)?;
+    for name in serials.iter().map(|x| x.name()) {
+        if new_data.down.contains_key(name) {
+            continue;
+        }
+        match config.check_controller(name, &scanned_channels(&new_data.get_data())) {
+            // nothing of a controller is controlled before its detectors are found as configured
+            Err(e) if !initialized.contains(name) => {
+                log::error!("{} is not initialized: {}", name, e);
+                new_data.set_down(name, e.to_string());
+            }
+            // the modules are already powered, so a changed polarity is only reported
+            Err(e) => log::warn!("{}", e),
+            Ok(()) => (),
+        }
+    }
+    if FIRST_SCAN.get().is_none() && new_data.down.len() == serials.len() {
+        let reasons = new_data
+            .down
+            .iter()
+            .map(|(name, reason)| format!("{}: {}", name, reason))
+            .collect::<Vec<_>>();
+        DATA.get()
+            .ok_or(OperationError::SharedDataError)?
+            .lock()?
+            .down = new_data.down;
+        return Err(OperationError::Unavailable(reasons.join("; ")));
+    }
+    let scanned = serials
+        .iter()
+        .map(|x| x.name().to_string())
+        .filter(|x| !new_data.down.contains_key(x))
+        .collect::<Vec<_>>();
+    let mhv4_data_array = new_data.get_data();
+    let channels = channel_ids(&mhv4_data_array);
+    // all checked before anything is replaced
+    let first_scan = FIRST_SCAN.get().unwrap_or(&channels).clone();
+    let configs = match_config(&mhv4_data_array)?;
+    let limits = voltage_limits(&channels, &first_scan, &configs)?;
+    let mut detector = trip_detector(&channels, &first_scan)?;
     {
-        let mut port = PORT.get().ok_or(OperationError::PortGetError)?.lock()?;
-        port.write(command.as_bytes())?;
-        std::thread::sleep(Duration::from_millis(50));
+        let mut shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+        if shared_data.is_progress {
+            return Err(OperationError::RampInProgress);
+        }
+        // the poller reads the same channels until the next reading
+        if channels == old_channels {
+            new_data.voltages = shared_data.voltages.clone();
+            new_data.currents = shared_data.currents.clone();
+        }
+        new_data.links = shared_data.links.clone();
+        *shared_data = new_data;
+    }
+    let _ = FIRST_SCAN.set(first_scan);
+    INITIALIZED.lock()?.extend(scanned);
+    *CONFIG.lock()? = configs;
+    log::info!("voltage limits: {:?}", limits);
+    *LIMITS.get().ok_or(OperationError::ArgumentError)?.lock()? = limits;
+    // the tripped channels are not armed again by a rescan
+    let mut trip = TRIP.get().ok_or(OperationError::SharedDataError)?.lock()?;
+    detector.keep_state(&trip, &old_channels, &channels);
+    *trip = detector;
+    drop(trip);
 
-        size = port.read(buf.as_mut_slice())?;
-        std::thread::sleep(Duration::from_millis(10));
+    let event = ChannelsEvent {
+        added: channels
+            .iter()
+            .filter(|x| !old_channels.contains(x))
+            .cloned()
+            .collect(),
+        removed: old_channels
+            .iter()
+            .filter(|x| !channels.contains(x))
+            .cloned()
+            .collect(),
+        channels,
+    };
+    log::info!(
+        "{} channels are found, added: {:?}, removed: {:?}",
+        event.channels.len(),
+        event.added,
+        event.removed
+    );
+    let is_changed = !event.added.is_empty() || !event.removed.is_empty();
+    if let Some(tx) = CHANNEL_EVENTS.get().filter(|_| is_changed) {
+        // error only when no client is connected
+        let _ = tx.send(event);
     }
-    let bytes = &buf[..size];
-    let string = String::from_utf8(bytes.to_vec())?;
-    let read_array = string.split("\n\r").collect::<Vec<_>>();
-    let vec = read_array.iter().map(|&s| s.to_string()).collect();
+    Ok(true)
+}
+
+// abort any ramp, ramp all the channels to 0 V and switch them off
+async fn emergency_off() -> Result<bool, OperationError> {
+    log::warn!("Emergency ramp-down is requested!");
+    RAMP.get()
+        .ok_or(OperationError::SharedDataError)?
+        .emergency()
+        .await?;
+    Ok(true)
+}
+
+// "kill -USR1 <pid>" starts the emergency ramp-down
+fn start_signal_handler() -> Result<(), OperationError> {
+    let mut signal = unix::signal(unix::SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while signal.recv().await.is_some() {
+            log::warn!("SIGUSR1 is received");
+            if let Err(e) = emergency_off().await {
+                log::error!("Error: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
 
-    log::trace!("result: {:?}", vec);
+// opens the port of the controller, the actor owns it from now on
+fn spawn_serial(name: &str, url: String) -> Result<SerialHandle, OperationError> {
+    let args = ARGS.get().ok_or(OperationError::ArgumentError)?;
+    log::debug!("trying to open the port {} of {}...", url, name);
+    let port = match transport::open(&url, args.port_rate, Duration::from_millis(100)) {
+        // the server starts without the hardware, only a wrong URL is fatal
+        Err(e @ TransportError::InvalidUrl(_)) => return Err(e.into()),
+        Err(e) => {
+            log::error!("could not open the port of {}: {}", name, e);
+            Err(e)
+        }
+        Ok(port) => {
+            log::info!("{} is connected to {}", name, port.description());
+            Ok(port)
+        }
+    };
 
-    Ok(vec)
+    let port_rate = args.port_rate;
+    let reconnect = Reconnect {
+        open: Box::new(move || transport::open(&url, port_rate, Duration::from_millis(100))),
+        min_delay: Duration::from_millis(args.reconnect_min),
+        max_delay: Duration::from_millis(args.reconnect_max),
+        max_timeouts: args.max_timeouts,
+    };
+    Ok(SerialHandle::spawn(
+        name,
+        port,
+        reconnect,
+        Duration::from_millis(args.reply_timeout),
+        args.max_reply_size,
+    ))
 }
 
 #[tokio::main]
@@ -442,46 +1604,68 @@ async fn main() -> Result<(), OperationError> {
     // argument parser
     log::debug!("trying to get command line arguments...");
     let args = CLArguments::parse();
+    let ramp = RampEngine::new(
+        args.voltage_step,
+        args.waiting_time,
+        args.emergency_step,
+        args.emergency_waiting_time,
+    );
     ARGS.set(args).map_err(|_| OperationError::OnceLockError)?;
+    RAMP.set(ramp).map_err(|_| OperationError::OnceLockError)?;
     log::debug!("success to get command line arguments");
 
-    // port connection
-    log::debug!(
-        "trying to open serial port from {}...",
-        ARGS.get().ok_or(OperationError::ArgumentError)?.port_name
-    );
-    let port = serialport::new(
-        &ARGS.get().ok_or(OperationError::ArgumentError)?.port_name,
-        ARGS.get().ok_or(OperationError::ArgumentError)?.port_rate,
-    )
-    .stop_bits(serialport::StopBits::One)
-    .data_bits(serialport::DataBits::Eight)
-    .parity(serialport::Parity::None)
-    .timeout(Duration::from_millis(100))
-    .open()?;
-
-    // get Mutex key
-    let port = Arc::new(Mutex::new(port));
-    PORT.set(port).map_err(|_| OperationError::OnceLockError)?;
-    log::debug!("success to open serial port!");
+    // one serial actor per controller, the names are a part of the channel ids
+    let controller_urls = ARGS
+        .get()
+        .ok_or(OperationError::ArgumentError)?
+        .controller_urls();
+    for (i, (name, _)) in controller_urls.iter().enumerate() {
+        if controller_urls[..i].iter().any(|x| x.0 == *name) {
+            CLArguments::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("controller \"{}\" is given twice", name),
+                )
+                .exit();
+        }
+    }
+    let mut serials = Vec::new();
+    for (name, url) in controller_urls {
+        serials.push(spawn_serial(&name, url)?);
+    }
+    SERIAL
+        .set(serials)
+        .map_err(|_| OperationError::OnceLockError)?;
 
     // main
-    initialize_status().await?;
+    start_audit_log()?;
+    initialize_status()?;
+    load_config()?;
+    load_credentials()?;
+    start_voltage_limits()?;
+    start_trip_detector()?;
+    start_initializer()?;
+    start_poller()?;
+    start_link_monitor()?;
+    start_recorder()?;
+    start_signal_handler()?;
 
     log::info!("Setting the routing...");
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_headers(vec!["Content-Type"])
-        .allow_methods(vec!["POST"]);
+        .allow_headers(vec!["Content-Type", "Authorization"])
+        .allow_methods(vec!["GET", "POST", "PATCH"]);
 
     let mhv4_data_route = warp::path("mhv4_data")
         .and(warp::get())
+        .and(auth::with_role(Role::Observer))
         .and_then(get_mhv4_data)
         .with(cors.clone());
 
     let sse_route = warp::path("sse")
         .and(warp::get())
-        .map(|| {
+        .and(auth::with_role(Role::Observer))
+        .map(|_user: String| {
             let stream = get_sse_stream();
             warp::sse::reply(warp::sse::keep_alive().stream(stream))
         })
@@ -489,49 +1673,125 @@ async fn main() -> Result<(), OperationError> {
 
     let status_route = warp::path("status")
         .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
         .and(warp::body::json())
-        .map(move |do_rc: bool| {
-            let result = match set_rcstatus(do_rc) {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Error: {:?}", e);
-                    false
-                }
-            };
-            warp::reply::json(&result)
-        })
+        .and_then(
+            |user: String, address: Option<SocketAddr>, do_rc: bool| async move {
+                let result = control(&user, address, ControlRequest::Status(do_rc)).await;
+                Ok::<_, warp::Rejection>(json_reply(result))
+            },
+        )
         .with(cors.clone());
 
     let onoff_route = warp::path("onoff")
         .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
         .and(warp::body::json())
-        .map(move |arr: Vec<bool>| {
-            let result = match set_onoff(arr) {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Error: {:?}", e);
-                    false
-                }
-            };
-            warp::reply::json(&result)
-        })
+        .and_then(
+            |user: String, address: Option<SocketAddr>, arr: Vec<bool>| async move {
+                let result = control(&user, address, ControlRequest::Onoff(arr)).await;
+                Ok::<_, warp::Rejection>(json_reply(result))
+            },
+        )
         .with(cors.clone());
 
     let apply_route = warp::path("apply")
         .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
         .and(warp::body::json())
-        .map(move |nums: Vec<isize>| {
-            let result = match set_voltage(nums) {
-                Ok(val) => val,
-                Err(e) => {
-                    log::error!("Error: {:?}", e);
-                    false
-                }
-            };
-            warp::reply::json(&result)
+        .and_then(
+            |user: String, address: Option<SocketAddr>, request: ApplyRequest| async move {
+                let result = control(&user, address, ControlRequest::Apply(request)).await;
+                Ok::<_, warp::Rejection>(json_reply(result))
+            },
+        )
+        .with(cors.clone());
+
+    let emergency_route = warp::path!("emergency")
+        .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
+        .and_then(|user: String, address: Option<SocketAddr>| async move {
+            let result = control(&user, address, ControlRequest::Emergency).await;
+            Ok::<_, warp::Rejection>(json_reply(result))
+        })
+        .with(cors.clone());
+
+    let rescan_route = warp::path!("rescan")
+        .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
+        .and_then(|user: String, address: Option<SocketAddr>| async move {
+            let result = control(&user, address, ControlRequest::Rescan).await;
+            Ok::<_, warp::Rejection>(json_reply(result))
         })
         .with(cors.clone());
 
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and_then(|_user: String| async move {
+            let text = metrics::render().map_err(warp::reject::custom)?;
+            Ok::<_, warp::Rejection>(warp::reply::with_header(
+                text,
+                "Content-Type",
+                "text/plain; version=0.0.4",
+            ))
+        })
+        .with(cors.clone());
+
+    let history_route = warp::path!("history")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and(warp::query::<HistoryQuery>())
+        .and_then(get_history)
+        .with(cors.clone());
+
+    let config_route = warp::path!("config")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and_then(get_config)
+        .with(cors.clone());
+
+    let trip_route = warp::path!("trip")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and_then(get_trip_status)
+        .with(cors.clone());
+
+    let api_route = api::routes().with(cors.clone());
+
+    let audit_route = warp::path!("audit")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and(warp::query::<AuditQuery>())
+        .and_then(get_audit)
+        .with(cors.clone());
+
+    let ramp_status_route = warp::path!("ramp")
+        .and(warp::get())
+        .and(auth::with_role(Role::Observer))
+        .and_then(get_ramp_status)
+        .with(cors.clone());
+
+    let ramp_control_route = warp::path!("ramp" / String)
+        .and(warp::post())
+        .and(auth::with_role(Role::Operator))
+        .and(warp::addr::remote())
+        .and_then(
+            |operation: String, user: String, address: Option<SocketAddr>| async move {
+                let result = control(&user, address, ControlRequest::Ramp(operation)).await;
+                Ok::<_, warp::Rejection>(json_reply(result))
+            },
+        )
+        .with(cors.clone());
+
+    let ws_route = ws::route().with(cors.clone());
+
+    let http_port = ARGS.get().ok_or(OperationError::ArgumentError)?.http_port;
     if ARGS
         .get()
         .ok_or(OperationError::ArgumentError)?
@@ -543,17 +1803,41 @@ async fn main() -> Result<(), OperationError> {
             .or(sse_route)
             .or(status_route)
             .or(onoff_route)
-            .or(apply_route);
+            .or(apply_route)
+            .or(ramp_status_route)
+            .or(ramp_control_route)
+            .or(emergency_route)
+            .or(rescan_route)
+            .or(trip_route)
+            .or(config_route)
+            .or(history_route)
+            .or(audit_route)
+            .or(api_route)
+            .or(ws_route)
+            .or(metrics_route)
+            .recover(shared::handle_rejection);
 
-        warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
+        warp::serve(routes).run(([0, 0, 0, 0], http_port)).await;
     } else {
         let routes = mhv4_data_route
             .or(sse_route)
             .or(status_route)
             .or(onoff_route)
-            .or(apply_route);
+            .or(apply_route)
+            .or(ramp_status_route)
+            .or(ramp_control_route)
+            .or(emergency_route)
+            .or(rescan_route)
+            .or(trip_route)
+            .or(config_route)
+            .or(history_route)
+            .or(audit_route)
+            .or(api_route)
+            .or(ws_route)
+            .or(metrics_route)
+            .recover(shared::handle_rejection);
 
-        warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
+        warp::serve(routes).run(([0, 0, 0, 0], http_port)).await;
     }
 
     Ok(())
@@ -0,0 +1,210 @@
+use crate::serial::{LinkState, SerialHandle, SerialStats, LATENCY_BUCKETS, SERIAL};
+use crate::shared::{OperationError, SharedData};
+use crate::{channel_config, DATA, READ_ERROR_VALUE, TRIP};
+use std::fmt::Write;
+
+// one counter of the serial stats
+type StatsField = fn(&SerialStats) -> u64;
+
+/// Prometheus text exposition of the latest reading and the serial health
+pub fn render() -> Result<String, OperationError> {
+    let shared_data: SharedData = DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .clone();
+    let tripped = match TRIP.get() {
+        Some(trip) => trip.lock()?.tripped(),
+        None => Vec::new(),
+    };
+
+    let mut text = String::new();
+    let mhv4_data_array = shared_data.get_data();
+    let labels = mhv4_data_array
+        .iter()
+        .enumerate()
+        .map(|(i, mhv4_data)| {
+            let (bus, dev, ch) = mhv4_data.get_module_id();
+            let name = channel_config(i).map(|x| x.name).unwrap_or_default();
+            format!(
+                "controller=\"{}\",bus=\"{}\",dev=\"{}\",ch=\"{}\",name=\"{}\"",
+                mhv4_data.controller,
+                bus,
+                dev,
+                ch,
+                escape(&name)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // the register units are 0.1 V and 1 nA
+    let channel_gauges: [(&str, &str, Vec<Option<f64>>); 6] = [
+        (
+            "mhv4_voltage_volts",
+            "Measured output voltage.",
+            readings(&shared_data.voltages, 10.0),
+        ),
+        (
+            "mhv4_current_amperes",
+            "Measured output current.",
+            readings(&shared_data.currents, 1e9),
+        ),
+        (
+            "mhv4_setpoint_volts",
+            "Voltage setpoint sent to the module.",
+            mhv4_data_array
+                .iter()
+                .map(|x| Some(x.get_current() as f64 / 10.0))
+                .collect(),
+        ),
+        (
+            "mhv4_channel_on",
+            "1 if the channel is switched on.",
+            mhv4_data_array
+                .iter()
+                .map(|x| Some(x.is_on as u8 as f64))
+                .collect(),
+        ),
+        (
+            "mhv4_polarity_positive",
+            "1 if the polarity is positive.",
+            mhv4_data_array
+                .iter()
+                .map(|x| Some(x.is_positive as u8 as f64))
+                .collect(),
+        ),
+        (
+            "mhv4_channel_tripped",
+            "1 if the channel is tripped by the over-current.",
+            tripped.iter().map(|&x| Some(x as u8 as f64)).collect(),
+        ),
+    ];
+    for (name, help, values) in channel_gauges.iter() {
+        header(&mut text, name, help, "gauge");
+        for (label, value) in labels.iter().zip(values.iter()) {
+            if let Some(value) = value {
+                let _ = writeln!(text, "{}{{{}}} {}", name, label, value);
+            }
+        }
+    }
+
+    header(
+        &mut text,
+        "mhv4_rc_on",
+        "1 if the remote control is on.",
+        "gauge",
+    );
+    let _ = writeln!(text, "mhv4_rc_on {}", shared_data.is_rc as u8);
+    header(
+        &mut text,
+        "mhv4_ramp_in_progress",
+        "1 while a ramp is running.",
+        "gauge",
+    );
+    let _ = writeln!(
+        text,
+        "mhv4_ramp_in_progress {}",
+        shared_data.is_progress as u8
+    );
+
+    if let Some(serials) = SERIAL.get() {
+        render_serial(&mut text, serials);
+    }
+    Ok(text)
+}
+
+// one series per controller
+fn render_serial(text: &mut String, serials: &[SerialHandle]) {
+    let all_stats = serials
+        .iter()
+        .map(|x| (format!("controller=\"{}\"", x.name()), x.stats()))
+        .collect::<Vec<_>>();
+    let counters: [(&str, &str, StatsField); 5] = [
+        (
+            "mhv4_serial_commands_total",
+            "Commands sent to the controller.",
+            |x| x.commands,
+        ),
+        (
+            "mhv4_serial_parse_errors_total",
+            "Replies that could not be parsed.",
+            |x| x.parse_errors,
+        ),
+        (
+            "mhv4_serial_timeouts_total",
+            "Replies that did not arrive in time.",
+            |x| x.timeouts,
+        ),
+        (
+            "mhv4_serial_io_errors_total",
+            "Other errors of the serial link.",
+            |x| x.io_errors,
+        ),
+        (
+            "mhv4_serial_reconnects_total",
+            "Times the serial port was opened again after the link was lost.",
+            |x| x.reconnects,
+        ),
+    ];
+    for (name, help, value) in counters {
+        header(text, name, help, "counter");
+        for (label, stats) in all_stats.iter() {
+            let _ = writeln!(text, "{}{{{}}} {}", name, label, value(stats));
+        }
+    }
+
+    let name = "mhv4_serial_latency_seconds";
+    header(
+        text,
+        name,
+        "Time from the command to the prompt.",
+        "histogram",
+    );
+    for (label, stats) in all_stats.iter() {
+        for (le, count) in LATENCY_BUCKETS.iter().zip(stats.latency_buckets.iter()) {
+            let _ = writeln!(text, "{}_bucket{{{},le=\"{}\"}} {}", name, label, le, count);
+        }
+        let _ = writeln!(
+            text,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, label, stats.commands
+        );
+        let _ = writeln!(text, "{}_sum{{{}}} {}", name, label, stats.latency_sum);
+        let _ = writeln!(text, "{}_count{{{}}} {}", name, label, stats.commands);
+    }
+
+    header(
+        text,
+        "mhv4_serial_connected",
+        "1 if the serial link is up.",
+        "gauge",
+    );
+    for (label, serial) in all_stats.iter().map(|x| &x.0).zip(serials.iter()) {
+        let _ = writeln!(
+            text,
+            "mhv4_serial_connected{{{}}} {}",
+            label,
+            (serial.link() == LinkState::Connected) as u8
+        );
+    }
+}
+
+fn header(text: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+}
+
+// read errors are not exported
+fn readings(values: &[isize], per_unit: f64) -> Vec<Option<f64>> {
+    values
+        .iter()
+        .map(|&x| Some(x as f64 / per_unit).filter(|_| x != READ_ERROR_VALUE))
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
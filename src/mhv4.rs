@@ -1,7 +1,9 @@
+use mhv4_monitor::channel::ChannelId;
 use serde::Serialize;
 
-#[derive(Serialize, Debug, Clone, Copy)]
+#[derive(Serialize, Debug, Clone)]
 pub struct MHV4Data {
+    pub controller: String,
     pub idc: usize,
     pub bus: usize,
     pub dev: usize,
@@ -13,30 +15,33 @@ pub struct MHV4Data {
 
 impl MHV4Data {
     pub fn new(
+        in_id: ChannelId,
         in_idc: usize,
-        in_bus: usize,
-        in_dev: usize,
-        in_ch: usize,
         in_current: isize,
         in_is_on: bool,
         in_is_positive: bool,
     ) -> MHV4Data {
         MHV4Data {
+            controller: in_id.controller,
             idc: in_idc,
-            bus: in_bus,
-            dev: in_dev,
-            ch: in_ch,
+            bus: in_id.bus,
+            dev: in_id.dev,
+            ch: in_id.ch,
             current: in_current,
             is_on: in_is_on,
             is_positive: in_is_positive,
         }
     }
 
-    pub fn get_module_id(self) -> (usize, usize, usize) {
+    pub fn get_module_id(&self) -> (usize, usize, usize) {
         (self.bus, self.dev, self.ch)
     }
 
-    pub fn get_current(self) -> isize {
+    pub fn channel_id(&self) -> ChannelId {
+        ChannelId::new(&self.controller, self.bus, self.dev, self.ch)
+    }
+
+    pub fn get_current(&self) -> isize {
         self.current
     }
 
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+pub const PROMPT: &str = "mrc-1>";
+pub const LINE_END: &str = "\n\r";
+pub const BUS_NUM: usize = 2;
+pub const DEV_NUM: usize = 16;
+pub const CH_NUM: usize = 4;
+
+// MHV4 register map (per channel registers use "base + ch")
+pub const REG_SETPOINT: usize = 0;
+pub const REG_ONOFF: usize = 4;
+pub const REG_CURRENT_LIMIT: usize = 8;
+pub const REG_READBACK: usize = 32;
+pub const REG_STATUS: usize = 36;
+pub const REG_POLARITY: usize = 46;
+pub const REG_CURRENT: usize = 50;
+pub const REG_HV_RANGE: usize = 13; // IDC 17
+pub const REG_RAMP_SPEED: usize = 80; // IDC 27
+
+/// Commands of the MRC-1 text protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Scan {
+        bus: usize,
+    },
+    Read {
+        bus: usize,
+        dev: usize,
+        reg: usize,
+    },
+    Set {
+        bus: usize,
+        dev: usize,
+        reg: usize,
+        value: isize,
+    },
+    On {
+        bus: usize,
+        dev: usize,
+    },
+    Off {
+        bus: usize,
+        dev: usize,
+    },
+}
+
+impl Command {
+    /// the bytes sent to the controller, terminated by "\r"
+    pub fn to_wire(&self) -> String {
+        format!("{}\r", self)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Scan { bus } => write!(f, "sc {}", bus),
+            Command::Read { bus, dev, reg } => write!(f, "re {} {} {}", bus, dev, reg),
+            Command::Set {
+                bus,
+                dev,
+                reg,
+                value,
+            } => write!(f, "se {} {} {} {}", bus, dev, reg, value),
+            Command::On { bus, dev } => write!(f, "on {} {}", bus, dev),
+            Command::Off { bus, dev } => write!(f, "off {} {}", bus, dev),
+        }
+    }
+}
+
+/// Error of parsing a command line, ex. "se 0 1 0"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCommandError(pub String);
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid command \"{}\", use sc, re, se, on or off with the addresses",
+            self.0
+        )
+    }
+}
+
+impl Error for ParseCommandError {}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    /// "sc 0", "re 0 1 32", "se 0 1 0 100", "on 0 1" or "off 0 1"
+    fn from_str(s: &str) -> Result<Command, ParseCommandError> {
+        let invalid = || ParseCommandError(s.trim().to_string());
+        let tokens = s.split_whitespace().collect::<Vec<_>>();
+        let (name, args) = tokens.split_first().ok_or_else(invalid)?;
+        let below = |x: &str, num: usize| x.parse::<usize>().ok().filter(|&x| x < num);
+        let address = |args: &[&str]| -> Option<(usize, usize)> {
+            Some((
+                below(args.first()?, BUS_NUM)?,
+                below(args.get(1)?, DEV_NUM)?,
+            ))
+        };
+
+        let command = match (*name, args.len()) {
+            ("sc", 1) => below(args[0], BUS_NUM).map(|bus| Command::Scan { bus }),
+            ("re", 3) => address(args).and_then(|(bus, dev)| {
+                let reg = args[2].parse().ok()?;
+                Some(Command::Read { bus, dev, reg })
+            }),
+            ("se", 4) => address(args).and_then(|(bus, dev)| {
+                let reg = args[2].parse().ok()?;
+                let value = args[3].parse().ok()?;
+                Some(Command::Set {
+                    bus,
+                    dev,
+                    reg,
+                    value,
+                })
+            }),
+            ("on", 2) => address(args).map(|(bus, dev)| Command::On { bus, dev }),
+            ("off", 2) => address(args).map(|(bus, dev)| Command::Off { bus, dev }),
+            _ => None,
+        };
+        command.ok_or_else(invalid)
+    }
+}
+
+/// One occupied address of the "sc" reply
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanEntry {
+    pub dev: usize,
+    pub idc: usize,
+    pub is_on: bool,
+}
+
+/// The parsed reply, ex. {"value": 1234} as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Scan(Vec<ScanEntry>),
+    Value(isize),
+    Done,
+}
+
+impl Response {
+    pub fn value(&self) -> Option<isize> {
+        match *self {
+            Response::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the whole reply of the command: echo, reply lines and the prompt
+pub fn parse_response(command: &Command, reply: &str) -> Result<Response, ProtocolError> {
+    // the controller uses "\n\r", but accept any line ending from noisy lines
+    let lines = reply
+        .split(['\n', '\r'])
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    let (echo, rest) = lines.split_first().ok_or(ProtocolError::EmptyReply)?;
+    let (prompt, body) = rest.split_last().ok_or(ProtocolError::MissingPrompt)?;
+    if *prompt != PROMPT {
+        return Err(ProtocolError::MissingPrompt);
+    }
+    let expected = command.to_string();
+    if *echo != expected {
+        return Err(ProtocolError::EchoMismatch {
+            expected,
+            found: echo.to_string(),
+        });
+    }
+    if let Some(line) = body.iter().find(|line| line.contains("ERROR")) {
+        return Err(ProtocolError::ErrorReply(line.to_string()));
+    }
+
+    match *command {
+        Command::Scan { bus } => parse_scan(bus, body),
+        Command::Read { bus, dev, reg } => parse_value(bus, dev, reg, body).map(Response::Value),
+        Command::Set {
+            bus,
+            dev,
+            reg,
+            value,
+        } => {
+            let set_value = parse_value(bus, dev, reg, body)?;
+            if set_value != value {
+                return Err(ProtocolError::Malformed(body.join(" ")));
+            }
+            Ok(Response::Value(set_value))
+        }
+        Command::On { .. } | Command::Off { .. } => Ok(Response::Done),
+    }
+}
+
+fn parse_scan(bus: usize, body: &[&str]) -> Result<Response, ProtocolError> {
+    let (header, devices) = body
+        .split_first()
+        .ok_or_else(|| ProtocolError::Malformed(String::new()))?;
+    if *header != format!("ID-SCAN BUS {}:", bus) || devices.len() != DEV_NUM {
+        return Err(ProtocolError::Malformed(body.join(" ")));
+    }
+
+    let mut modules: Vec<ScanEntry> = Vec::new();
+    for (dev, line) in devices.iter().enumerate() {
+        // "0: 27, ON" or "1: -"
+        let datas = line.split_whitespace().collect::<Vec<_>>();
+        if datas.first() != Some(&format!("{}:", dev).as_str()) {
+            return Err(ProtocolError::Malformed(line.to_string()));
+        }
+        match datas.as_slice() {
+            [_, "-"] => continue,
+            [_, idc, power] => {
+                let idc = idc
+                    .trim_end_matches(',')
+                    .parse()
+                    .map_err(|_| ProtocolError::Malformed(line.to_string()))?;
+                modules.push(ScanEntry {
+                    dev,
+                    idc,
+                    is_on: *power == "ON",
+                });
+            }
+            _ => return Err(ProtocolError::Malformed(line.to_string())),
+        }
+    }
+    Ok(Response::Scan(modules))
+}
+
+// "RE 0 1 32 1234", the value is the last token
+fn parse_value(bus: usize, dev: usize, reg: usize, body: &[&str]) -> Result<isize, ProtocolError> {
+    let line = match body {
+        [line] => line,
+        _ => return Err(ProtocolError::Malformed(body.join(" "))),
+    };
+    let datas = line.split_whitespace().collect::<Vec<_>>();
+    let (value, address) = datas
+        .split_last()
+        .ok_or_else(|| ProtocolError::Malformed(line.to_string()))?;
+    let value = value
+        .parse()
+        .map_err(|_| ProtocolError::Malformed(line.to_string()))?;
+
+    // if the address is in the reply, it should be the requested one
+    if address.len() >= 3 {
+        let numbers = address[address.len() - 3..]
+            .iter()
+            .map(|x| x.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+        if let Ok(numbers) = numbers {
+            if numbers != [bus, dev, reg] {
+                return Err(ProtocolError::Malformed(line.to_string()));
+            }
+        }
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    EmptyReply,
+    MissingPrompt,
+    EchoMismatch { expected: String, found: String },
+    ErrorReply(String),
+    Malformed(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::EmptyReply => write!(f, "No reply from the controller"),
+            ProtocolError::MissingPrompt => write!(f, "Reply without the prompt"),
+            ProtocolError::EchoMismatch {
+                ref expected,
+                ref found,
+            } => write!(f, "Echo mismatch: sent \"{}\", got \"{}\"", expected, found),
+            ProtocolError::ErrorReply(ref line) => write!(f, "Controller error: {}", line),
+            ProtocolError::Malformed(ref line) => write!(f, "Malformed reply: {}", line),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
@@ -0,0 +1,313 @@
+use crate::mhv4::MHV4Data;
+use crate::serial::Priority;
+use crate::shared::OperationError;
+use crate::{set_register_with, DATA};
+use mhv4_monitor::protocol::{REG_ONOFF, REG_SETPOINT};
+use mhv4_monitor::ramping::{self, ChannelProgress, RampPlan, RampState, RampStatus, RampWrite};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+/// Steps every channel toward its target, one ramp at a time
+pub struct RampEngine {
+    status: Mutex<RampStatus>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    // notified at every request on the status, wakes the task between the steps
+    changed: Notify,
+    normal: RampPlan,
+    emergency: RampPlan,
+}
+
+impl RampEngine {
+    pub fn new(
+        in_step: isize,
+        in_waiting_time: u64,
+        in_emergency_step: isize,
+        in_emergency_waiting_time: u64,
+    ) -> RampEngine {
+        RampEngine {
+            status: Mutex::new(RampStatus::default()),
+            task: Mutex::new(None),
+            changed: Notify::new(),
+            normal: RampPlan {
+                step: in_step,
+                waiting_time: in_waiting_time,
+                switch_off: false,
+            },
+            emergency: RampPlan {
+                step: in_emergency_step,
+                waiting_time: in_emergency_waiting_time,
+                switch_off: true,
+            },
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RampStatus> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn status(&self) -> RampStatus {
+        self.lock().clone()
+    }
+
+    /// one "step" every "waiting_time" ms
+    pub fn start(
+        &'static self,
+        mhv4_data_array: Vec<MHV4Data>,
+        targets: Vec<isize>,
+        step: isize,
+        waiting_time: u64,
+    ) -> Result<(), OperationError> {
+        let plan = RampPlan {
+            step,
+            waiting_time,
+            ..self.normal
+        };
+        // checked and started under the same lock, so only one of the requests starts
+        let status = self.lock();
+        if status.is_emergency {
+            return Err(OperationError::RampInProgress);
+        }
+        self.launch(status, mhv4_data_array, targets, plan)
+    }
+
+    /// Aborts the running ramp, ramps all the channels to 0 V and switches them off
+    pub async fn emergency(&'static self) -> Result<(), OperationError> {
+        // normal ramps are refused from now on
+        if !self.lock().request_emergency() {
+            log::warn!("Emergency ramp-down is already running");
+            return Ok(());
+        }
+        self.cancel().await;
+
+        let result = shared_data().and_then(|mhv4_data_array| {
+            let targets = vec![0; mhv4_data_array.len()];
+            self.launch(self.lock(), mhv4_data_array, targets, self.emergency)
+        });
+        if result.is_err() {
+            self.lock().is_emergency = false;
+        }
+        result
+    }
+
+    /// Ramps one channel down to "safe_voltage", a running ramp is aborted
+    /// and restarted with the same targets for the other channels
+    pub async fn ramp_down(
+        &'static self,
+        index: usize,
+        safe_voltage: isize,
+    ) -> Result<(), OperationError> {
+        let targets = {
+            let mut status = self.lock();
+            if status.is_emergency {
+                return Err(OperationError::RampInProgress);
+            }
+            if status.is_running() {
+                status.state = RampState::Aborted;
+                Some(status.channels.iter().map(|x| x.target).collect::<Vec<_>>())
+            } else {
+                None
+            }
+        };
+        self.wait_stopped().await;
+
+        let mhv4_data_array = shared_data()?;
+        let mut targets = targets.unwrap_or_else(|| {
+            mhv4_data_array
+                .iter()
+                .map(|mhv4_data| mhv4_data.get_current())
+                .collect()
+        });
+        let current = mhv4_data_array
+            .get(index)
+            .ok_or(OperationError::ArgumentError)?
+            .get_current();
+        // never ramps up the tripped channel
+        targets[index] = current.min(safe_voltage);
+        let status = self.lock();
+        // the emergency ramp-down may be requested while waiting
+        if status.is_emergency {
+            return Err(OperationError::RampInProgress);
+        }
+        self.launch(status, mhv4_data_array, targets, self.normal)
+    }
+
+    // the running ramp is dropped at once, even in the middle of a command,
+    // the module may have one more step than the shared data
+    async fn cancel(&self) {
+        let task = self
+            .task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(task) = task {
+            task.abort();
+            if task.await.is_err() {
+                log::warn!("Ramp is cancelled");
+                // "finish" of the cancelled task, "is_emergency" is kept
+                self.lock().finish(RampState::Aborted, self.normal);
+                if let Err(e) = set_progress(false) {
+                    log::error!("Could not update the progress: {}", e);
+                }
+            }
+        }
+    }
+
+    // the aborted ramp stops after the command in flight,
+    // the task is kept for the other waiters and for "launch"
+    async fn wait_stopped(&self) {
+        self.changed.notify_one();
+        while self.is_task_running() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn is_task_running(&self) -> bool {
+        self.task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|x| !x.is_finished())
+    }
+
+    // the lock is held until the task is stored
+    fn launch(
+        &'static self,
+        mut status: MutexGuard<'_, RampStatus>,
+        mhv4_data_array: Vec<MHV4Data>,
+        targets: Vec<isize>,
+        plan: RampPlan,
+    ) -> Result<(), OperationError> {
+        if plan.step <= 0 {
+            return Err(OperationError::ArgumentError);
+        }
+        // an aborted ramp would still write its last step and its state
+        if self.is_task_running() {
+            return Err(OperationError::RampInProgress);
+        }
+        let channels = mhv4_data_array
+            .iter()
+            .zip(targets.iter())
+            .map(|(mhv4_data, &target)| ChannelProgress {
+                current: mhv4_data.get_current(),
+                target,
+            })
+            .collect();
+        if !status.begin(channels, plan) {
+            return Err(OperationError::RampInProgress);
+        }
+        if let Err(e) = start_progress(&mhv4_data_array) {
+            status.finish(RampState::Failed, plan);
+            return Err(e);
+        }
+
+        let task = tokio::spawn(async move {
+            let channels = &mhv4_data_array;
+            let state = ramping::run(&self.status, &self.changed, plan, |index, write| {
+                write_channel(&channels[index], write, priority(plan))
+            })
+            .await;
+            if let Some(ref e) = self.lock().error {
+                log::error!("Ramp failed: {}", e);
+            }
+            log::info!("Ramp finished: {:?}", state);
+            self.lock().finish(state, plan);
+            if let Err(e) = set_progress(false) {
+                log::error!("Could not update the progress: {}", e);
+            }
+        });
+        *self.task.lock().unwrap_or_else(PoisonError::into_inner) = Some(task);
+        Ok(())
+    }
+
+    pub fn pause(&self) -> bool {
+        let is_done = self.lock().pause();
+        if is_done {
+            self.changed.notify_one();
+        }
+        is_done
+    }
+
+    pub fn resume(&self) -> bool {
+        let is_done = self.lock().resume();
+        if is_done {
+            self.changed.notify_one();
+        }
+        is_done
+    }
+
+    // the setpoints stay at the last step
+    pub fn abort(&self) -> bool {
+        let is_done = self.lock().abort();
+        if is_done {
+            self.changed.notify_one();
+        }
+        is_done
+    }
+}
+
+// one step or the switch-off of a channel, the shared data follows the module
+async fn write_channel(
+    mhv4_data: &MHV4Data,
+    write: RampWrite,
+    priority: Priority,
+) -> Result<(), OperationError> {
+    let (bus, dev, ch) = mhv4_data.get_module_id();
+    let controller = &mhv4_data.controller;
+    match write {
+        RampWrite::Setpoint(value) => {
+            set_register_with(controller, bus, dev, ch + REG_SETPOINT, value, priority).await?;
+            DATA.get()
+                .ok_or(OperationError::SharedDataError)?
+                .lock()?
+                .set_current(&mhv4_data.channel_id(), value);
+        }
+        RampWrite::Off => {
+            set_register_with(controller, bus, dev, ch + REG_ONOFF, 0, priority).await?;
+            DATA.get()
+                .ok_or(OperationError::SharedDataError)?
+                .lock()?
+                .set_onoff(&mhv4_data.channel_id(), false);
+        }
+    }
+    Ok(())
+}
+
+// the emergency ramp-down goes before the other commands
+fn priority(plan: RampPlan) -> Priority {
+    if plan.switch_off {
+        Priority::Emergency
+    } else {
+        Priority::Control
+    }
+}
+
+// the setpoints after the last ramp step
+fn shared_data() -> Result<Vec<MHV4Data>, OperationError> {
+    Ok(DATA
+        .get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .get_data())
+}
+
+// a rescan is refused from now on, or has already changed the channels of the ramp
+fn start_progress(mhv4_data_array: &[MHV4Data]) -> Result<(), OperationError> {
+    let mut shared_data = DATA.get().ok_or(OperationError::SharedDataError)?.lock()?;
+    if !shared_data.has_channels(mhv4_data_array) {
+        return Err(OperationError::RampStateError(String::from(
+            "The channels are changed by a rescan, send the request again",
+        )));
+    }
+    shared_data.is_progress = true;
+    Ok(())
+}
+
+fn set_progress(is_progress: bool) -> Result<(), OperationError> {
+    DATA.get()
+        .ok_or(OperationError::SharedDataError)?
+        .lock()?
+        .is_progress = is_progress;
+    Ok(())
+}
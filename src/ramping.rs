@@ -0,0 +1,233 @@
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Duration, Instant};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RampState {
+    #[default]
+    Idle,
+    Ramping,
+    Paused,
+    Aborted,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelProgress {
+    pub current: isize,
+    pub target: isize,
+}
+
+/// How one ramp is driven, "step" every "waiting_time" ms
+///
+/// The emergency ramp-down switches the channels off at 0 V.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RampPlan {
+    pub step: isize,
+    pub waiting_time: u64,
+    pub switch_off: bool,
+}
+
+/// One write of the ramp to a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RampWrite {
+    Setpoint(isize),
+    Off,
+}
+
+/// The state of the ramp engine, sent to the clients as it is
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RampStatus {
+    pub state: RampState,
+    pub is_emergency: bool,
+    pub channels: Vec<ChannelProgress>,
+    pub eta_ms: u64,
+    pub error: Option<String>,
+}
+
+impl RampStatus {
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, RampState::Ramping | RampState::Paused)
+    }
+
+    /// a new ramp of the channels, refused while another one is running
+    pub fn begin(&mut self, channels: Vec<ChannelProgress>, plan: RampPlan) -> bool {
+        if self.is_running() {
+            return false;
+        }
+        self.state = RampState::Ramping;
+        self.error = None;
+        self.eta_ms = eta(&channels, plan);
+        self.channels = channels;
+        true
+    }
+
+    /// the emergency ramp-down can not be paused
+    pub fn pause(&mut self) -> bool {
+        if self.state != RampState::Ramping || self.is_emergency {
+            return false;
+        }
+        self.state = RampState::Paused;
+        true
+    }
+
+    pub fn resume(&mut self) -> bool {
+        if self.state != RampState::Paused {
+            return false;
+        }
+        self.state = RampState::Ramping;
+        true
+    }
+
+    // the setpoints stay at the last step
+    pub fn abort(&mut self) -> bool {
+        if !self.is_running() || self.is_emergency {
+            return false;
+        }
+        self.state = RampState::Aborted;
+        true
+    }
+
+    /// aborts the running ramp and refuses the normal ones, false if it is already requested
+    pub fn request_emergency(&mut self) -> bool {
+        if self.is_emergency {
+            return false;
+        }
+        self.is_emergency = true;
+        if self.is_running() {
+            self.state = RampState::Aborted;
+        }
+        true
+    }
+
+    /// the ramp of "plan" is stopped in "state"
+    pub fn finish(&mut self, state: RampState, plan: RampPlan) {
+        self.state = state;
+        self.eta_ms = 0;
+        if plan.switch_off {
+            self.is_emergency = false;
+        }
+    }
+}
+
+/// time to the end of the ramp in ms, the slowest channel
+pub fn eta(channels: &[ChannelProgress], plan: RampPlan) -> u64 {
+    let steps = channels
+        .iter()
+        .map(|x| {
+            (x.target - x.current)
+                .unsigned_abs()
+                .div_ceil(plan.step.max(1) as usize)
+        })
+        .max()
+        .unwrap_or(0);
+    steps as u64 * plan.waiting_time
+}
+
+/// the setpoint after one step toward "target"
+pub fn next_step(current: isize, target: isize, step: isize) -> isize {
+    if (current - target).abs() < step {
+        target
+    } else if current < target {
+        current + step
+    } else {
+        current - step
+    }
+}
+
+fn lock(status: &Mutex<RampStatus>) -> MutexGuard<'_, RampStatus> {
+    status.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Steps the channels of "status" toward their targets, "write" sends one value to the channel of the index
+///
+/// "changed" is notified at every change of "status" by the requests, so a pause is resumed and an abort
+/// stops the wait between the steps at once. The first failed write stops a normal ramp. The emergency ramp-down gives up only that channel,
+/// goes on with the others and still switches every channel off, the errors are kept in "status.error".
+pub async fn run<F, Fut, E>(
+    status: &Mutex<RampStatus>,
+    changed: &Notify,
+    plan: RampPlan,
+    mut write: F,
+) -> RampState
+where
+    F: FnMut(usize, RampWrite) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: fmt::Display,
+{
+    let mut errors = Vec::new();
+    let mut is_failed = vec![false; lock(status).channels.len()];
+    loop {
+        // wait while paused, stop when aborted
+        let mut channels = loop {
+            let status = lock(status).clone();
+            match status.state {
+                RampState::Ramping => break status.channels,
+                RampState::Paused => changed.notified().await,
+                state => return state,
+            }
+        };
+        let is_done = |i: usize, x: &ChannelProgress| is_failed[i] || x.current == x.target;
+        if channels.iter().enumerate().all(|(i, x)| is_done(i, x)) {
+            break;
+        }
+
+        let started = Instant::now();
+        for (i, progress) in channels.iter_mut().enumerate() {
+            if is_failed[i] || progress.current == progress.target {
+                continue;
+            }
+            let next = next_step(progress.current, progress.target, plan.step);
+            if let Err(e) = write(i, RampWrite::Setpoint(next)).await {
+                let message = format!("channel {}: {}", i, e);
+                if !plan.switch_off {
+                    lock(status).error = Some(message);
+                    return RampState::Failed;
+                }
+                errors.push(message);
+                is_failed[i] = true;
+                continue;
+            }
+            progress.current = next;
+
+            let mut status = lock(status);
+            status.channels[i].current = next;
+            if status.state != RampState::Ramping {
+                break;
+            }
+        }
+        {
+            let mut status = lock(status);
+            status.eta_ms = eta(&status.channels, plan);
+        }
+
+        // one step of all the channels takes "waiting_time", a pause waits for it as well
+        let deadline = started + Duration::from_millis(plan.waiting_time);
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline) => break,
+                _ = changed.notified() => (),
+            }
+            if !lock(status).is_running() {
+                break;
+            }
+        }
+    }
+
+    if plan.switch_off {
+        for i in 0..is_failed.len() {
+            if let Err(e) = write(i, RampWrite::Off).await {
+                errors.push(format!("channel {}: {}", i, e));
+            }
+        }
+    }
+    if errors.is_empty() {
+        return RampState::Idle;
+    }
+    lock(status).error = Some(errors.join("; "));
+    RampState::Failed
+}
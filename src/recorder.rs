@@ -0,0 +1,344 @@
+use crate::channel::ChannelId;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILE_PREFIX: &str = "mhv4_";
+const RAW_SUFFIX: &str = ".csv";
+const DOWNSAMPLED_SUFFIX: &str = ".ds.csv";
+
+/// One poll of all the channels, None when the value could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time_ms: u64,
+    pub voltages: Vec<Option<isize>>,
+    pub currents: Vec<Option<isize>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    /// a new file is started every "rotate_ms"
+    pub rotate_ms: u64,
+    /// files older than this are deleted
+    pub retention_ms: u64,
+    /// files older than this are reduced to the min/max of every "bucket_ms"
+    pub downsample_after_ms: u64,
+    pub bucket_ms: u64,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    channels: Vec<ChannelId>,
+    end_ms: u64,
+}
+
+/// Writes every poll to rotating CSV files in "dir"
+///
+/// The header has the channel identity, "time_ms,voltage target:0:1:2,current target:0:1:2,...",
+/// and an empty field is a read error.
+pub struct Recorder {
+    config: RecorderConfig,
+    file: Option<OpenFile>,
+}
+
+impl Recorder {
+    pub fn new(in_config: RecorderConfig) -> io::Result<Recorder> {
+        fs::create_dir_all(&in_config.dir)?;
+        Ok(Recorder {
+            config: in_config,
+            file: None,
+        })
+    }
+
+    pub fn record(&mut self, channels: &[ChannelId], record: &Record) -> io::Result<()> {
+        // the topology or the period is changed
+        let is_new_file = match self.file {
+            Some(ref file) => file.channels != channels || record.time_ms >= file.end_ms,
+            None => true,
+        };
+        if is_new_file {
+            self.file = Some(self.create(channels, record.time_ms)?);
+        }
+
+        if let Some(ref mut file) = self.file {
+            writeln!(file.writer, "{}", format_record(record))?;
+            // nothing is lost when the server is killed
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // a new topology or a restart in the same second gets its own file, ex. "mhv4_..._130512_01.csv"
+    fn create(&self, channels: &[ChannelId], time_ms: u64) -> io::Result<OpenFile> {
+        let name = format!("{}{}", FILE_PREFIX, format_time(time_ms));
+        let mut count = 0;
+        let (path, file) = loop {
+            let path = match count {
+                0 => self.config.dir.join(format!("{}{}", name, RAW_SUFFIX)),
+                _ => self
+                    .config
+                    .dir
+                    .join(format!("{}_{:02}{}", name, count, RAW_SUFFIX)),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => count += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        log::info!("recording to {}", path.display());
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", format_header(channels))?;
+
+        let rotate_ms = self.config.rotate_ms.max(1);
+        Ok(OpenFile {
+            path,
+            writer,
+            channels: channels.to_vec(),
+            end_ms: (time_ms / rotate_ms + 1) * rotate_ms,
+        })
+    }
+
+    /// deletes the expired files and downsamples the old ones, using the last modified time
+    pub fn maintain(&mut self, now_ms: u64) -> io::Result<()> {
+        for path in record_files(&self.config.dir)? {
+            if self.file.as_ref().is_some_and(|x| x.path == path) {
+                continue;
+            }
+            let modified = fs::metadata(&path)?.modified()?;
+            let age_ms = now_ms.saturating_sub(modified_ms(&path)?);
+
+            if age_ms > self.config.retention_ms {
+                log::info!("deleting the expired record {}", path.display());
+                fs::remove_file(&path)?;
+            } else if age_ms > self.config.downsample_after_ms && !is_downsampled(&path) {
+                downsample_file(&path, self.config.bucket_ms, modified)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// record files in "dir", oldest first
+pub fn record_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|x| x.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| x.starts_with(FILE_PREFIX) && x.ends_with(RAW_SUFFIX))
+        })
+        .collect::<Vec<_>>();
+    // the name starts with the date
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn is_downsampled(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|x| x.ends_with(DOWNSAMPLED_SUFFIX))
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<(Vec<ChannelId>, Vec<Record>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = match lines.next() {
+        Some(line) => line?,
+        None => return Ok((Vec::new(), Vec::new())),
+    };
+    let channels = parse_header(&header)?;
+
+    let mut records: Vec<Record> = Vec::new();
+    for line in lines {
+        // the last line may be cut by a crash
+        if let Some(record) = parse_record(&line?, channels.len()) {
+            records.push(record);
+        }
+    }
+    Ok((channels, records))
+}
+
+/// min and max of every column in each bucket, so the peaks are kept
+pub fn downsample(records: &[Record], bucket_ms: u64) -> Vec<Record> {
+    let bucket_ms = bucket_ms.max(1);
+    let mut result: Vec<Record> = Vec::new();
+    for bucket in records.chunk_by(|a, b| a.time_ms / bucket_ms == b.time_ms / bucket_ms) {
+        if bucket.len() <= 2 {
+            result.extend_from_slice(bucket);
+            continue;
+        }
+        let start = bucket[0].time_ms / bucket_ms * bucket_ms;
+        let column = |f: fn(&Record) -> &Vec<Option<isize>>, is_max: bool| {
+            (0..f(&bucket[0]).len())
+                .map(|i| {
+                    let values = bucket.iter().filter_map(|x| f(x).get(i).copied().flatten());
+                    if is_max {
+                        values.max()
+                    } else {
+                        values.min()
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        result.push(Record {
+            time_ms: start,
+            voltages: column(|x| &x.voltages, false),
+            currents: column(|x| &x.currents, false),
+        });
+        result.push(Record {
+            time_ms: start + bucket_ms / 2,
+            voltages: column(|x| &x.voltages, true),
+            currents: column(|x| &x.currents, true),
+        });
+    }
+    result
+}
+
+fn downsample_file(path: &Path, bucket_ms: u64, modified: SystemTime) -> io::Result<()> {
+    log::info!("downsampling the record {}", path.display());
+    let (channels, records) = read_file(path)?;
+    let records = downsample(&records, bucket_ms);
+
+    let name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.strip_suffix(RAW_SUFFIX))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a record file"))?;
+    let new_path = path.with_file_name(format!("{}{}", name, DOWNSAMPLED_SUFFIX));
+
+    let mut writer = BufWriter::new(File::create(&new_path)?);
+    writeln!(writer, "{}", format_header(&channels))?;
+    for record in records.iter() {
+        writeln!(writer, "{}", format_record(record))?;
+    }
+    // keeps the age for the retention
+    writer.into_inner()?.set_modified(modified)?;
+    fs::remove_file(path)
+}
+
+fn format_header(channels: &[ChannelId]) -> String {
+    let mut header = String::from("time_ms");
+    for id in channels {
+        header.push_str(&format!(",voltage {0},current {0}", id));
+    }
+    header
+}
+
+fn parse_header(header: &str) -> io::Result<Vec<ChannelId>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid record header");
+    let columns = header.split(',').collect::<Vec<_>>();
+    if columns.first() != Some(&"time_ms") || columns.len() % 2 != 1 {
+        return Err(invalid());
+    }
+
+    columns[1..]
+        .chunks(2)
+        .map(|pair| {
+            // "0:1:2" of the files before the controllers
+            pair[0]
+                .strip_prefix("voltage ")
+                .and_then(|x| x.parse::<ChannelId>().ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn format_record(record: &Record) -> String {
+    let mut line = record.time_ms.to_string();
+    for (voltage, current) in record.voltages.iter().zip(record.currents.iter()) {
+        for value in [voltage, current] {
+            line.push(',');
+            if let Some(value) = value {
+                line.push_str(&value.to_string());
+            }
+        }
+    }
+    line
+}
+
+fn parse_record(line: &str, ch_num: usize) -> Option<Record> {
+    let mut fields = line.split(',');
+    let time_ms = fields.next()?.parse().ok()?;
+    let values = fields
+        .map(|x| match x {
+            "" => Ok(None),
+            x => x.parse().map(Some),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    if values.len() != ch_num * 2 {
+        return None;
+    }
+    Some(Record {
+        time_ms,
+        voltages: values.iter().step_by(2).copied().collect(),
+        currents: values.iter().skip(1).step_by(2).copied().collect(),
+    })
+}
+
+/// "2026-10-18_130512" in UTC
+pub fn format_time(time_ms: u64) -> String {
+    let secs = time_ms / 1000;
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+
+    // days to the civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// the end of the data in the file
+pub fn modified_ms<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    Ok(fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0))
+}
+
+/// start time of the record file given by its name
+pub fn file_start_ms(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?.strip_prefix(FILE_PREFIX)?;
+    // "2026-10-18_130512"
+    let (date, time) = name.get(..17)?.split_once('_')?;
+    let date = date
+        .split('-')
+        .map(|x| x.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (year, month, day) = match date.as_slice() {
+        &[year, month, day] => (year, month, day),
+        _ => return None,
+    };
+    let time = time.parse::<u64>().ok()?;
+
+    // civil date to days, from the same algorithms as "format_time"
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+    let secs = days * 86400 + time / 10000 * 3600 + time / 100 % 100 * 60 + time % 100;
+    Some(secs * 1000)
+}
@@ -0,0 +1,18 @@
+use crate::shared::OperationError;
+pub use mhv4_monitor::link::{
+    LinkState, Priority, Reconnect, SerialHandle, SerialStats, LATENCY_BUCKETS,
+};
+use std::sync::OnceLock;
+
+/// the serial actors of all the controllers, set once at the start
+pub static SERIAL: OnceLock<Vec<SerialHandle>> = OnceLock::new();
+
+/// the serial actor of the controller
+pub fn controller(name: &str) -> Result<&'static SerialHandle, OperationError> {
+    SERIAL
+        .get()
+        .ok_or(OperationError::PortGetError)?
+        .iter()
+        .find(|x| x.name() == name)
+        .ok_or(OperationError::PortGetError)
+}
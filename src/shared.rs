@@ -1,16 +1,43 @@
+use crate::auth::AuthError;
 use crate::mhv4::MHV4Data;
+use crate::serial::LinkState;
 use clap::Parser;
+use mhv4_monitor::channel::{ChannelId, DEFAULT_CONTROLLER};
+use mhv4_monitor::config::ConfigError;
+use mhv4_monitor::credentials::CredentialsError;
+use mhv4_monitor::limits::LimitError;
+use mhv4_monitor::link::LinkError;
+use mhv4_monitor::protocol::ProtocolError;
+use mhv4_monitor::transport::TransportError;
+use mhv4_monitor::trip::TripAction;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
 use std::sync::MutexGuard;
 use std::sync::PoisonError;
+use warp::filters::body::BodyDeserializeError;
+use warp::http::StatusCode;
+use warp::reject::InvalidQuery;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
 
 #[derive(Serialize, Debug, Clone)]
 pub struct SharedData {
     mhv4_data_array: Vec<MHV4Data>,
     pub is_rc: bool,
     pub is_progress: bool,
+    // the serial link of every controller by its name
+    pub links: BTreeMap<String, LinkState>,
+    // why there is no channel, None once the modules are scanned
+    pub unavailable: Option<String>,
+    // the controllers which could not be scanned, with the reason
+    pub down: BTreeMap<String, String>,
+    // the latest reading of the poller, for "/metrics"
+    #[serde(skip)]
+    pub voltages: Vec<isize>,
+    #[serde(skip)]
+    pub currents: Vec<isize>,
 }
 
 impl SharedData {
@@ -19,6 +46,11 @@ impl SharedData {
             mhv4_data_array: in_vec,
             is_rc: in_is_rc,
             is_progress: false,
+            links: BTreeMap::new(),
+            unavailable: None,
+            down: BTreeMap::new(),
+            voltages: Vec::new(),
+            currents: Vec::new(),
         }
     }
 
@@ -26,15 +58,105 @@ impl SharedData {
         self.mhv4_data_array.clone()
     }
 
-    pub fn set_current(&mut self, id: usize, in_current: isize) {
-        self.mhv4_data_array[id].set_current(in_current);
+    // by the id, a channel removed by a rescan meanwhile is skipped
+    fn channel_mut(&mut self, id: &ChannelId) -> Option<&mut MHV4Data> {
+        self.mhv4_data_array
+            .iter_mut()
+            .find(|x| x.channel_id() == *id)
     }
 
-    pub fn set_onoff(&mut self, id: usize, do_on: bool) {
-        self.mhv4_data_array[id].is_on = do_on;
+    pub fn set_current(&mut self, id: &ChannelId, in_current: isize) {
+        if let Some(mhv4_data) = self.channel_mut(id) {
+            mhv4_data.set_current(in_current);
+        }
+    }
+
+    pub fn set_onoff(&mut self, id: &ChannelId, do_on: bool) {
+        if let Some(mhv4_data) = self.channel_mut(id) {
+            mhv4_data.is_on = do_on;
+        }
+    }
+
+    /// true if the channels are the same as "mhv4_data_array", ex. not changed by a rescan
+    pub fn has_channels(&self, mhv4_data_array: &[MHV4Data]) -> bool {
+        self.mhv4_data_array.len() == mhv4_data_array.len()
+            && self
+                .mhv4_data_array
+                .iter()
+                .zip(mhv4_data_array.iter())
+                .all(|(x, y)| x.channel_id() == y.channel_id())
+    }
+
+    // the channels of the controller are dropped until it is scanned again
+    pub fn set_down(&mut self, controller: &str, reason: String) {
+        self.mhv4_data_array.retain(|x| x.controller != controller);
+        self.down.insert(controller.to_string(), reason);
+    }
+
+    pub fn state(&self) -> StateEvent {
+        StateEvent {
+            is_rc: self.is_rc,
+            is_progress: self.is_progress,
+            links: self.links.clone(),
+            unavailable: self.unavailable.clone(),
+            down: self.down.clone(),
+            is_on: self.mhv4_data_array.iter().map(|x| x.is_on).collect(),
+            setpoints: self
+                .mhv4_data_array
+                .iter()
+                .map(|x| x.get_current())
+                .collect(),
+        }
     }
 }
 
+/// The modes, the hardware and the settings of all the channels, sent to the clients
+/// when changed
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateEvent {
+    pub is_rc: bool,
+    pub is_progress: bool,
+    pub links: BTreeMap<String, LinkState>,
+    pub unavailable: Option<String>,
+    pub down: BTreeMap<String, String>,
+    pub is_on: Vec<bool>,
+    pub setpoints: Vec<isize>,
+}
+
+/// The reading of one channel, the voltage in 0.1 V and the current in nA
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelReading {
+    #[serde(flatten)]
+    pub id: ChannelId,
+    pub voltage: isize,
+    pub current: isize,
+}
+
+/// One reading of every channel by the poller with the ramp progress, sent to the clients as it is
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MonitorValue {
+    pub channels: Vec<ChannelReading>,
+    pub is_progress: bool,
+}
+
+impl MonitorValue {
+    pub fn voltages(&self) -> Vec<isize> {
+        self.channels.iter().map(|x| x.voltage).collect()
+    }
+
+    pub fn currents(&self) -> Vec<isize> {
+        self.channels.iter().map(|x| x.current).collect()
+    }
+}
+
+/// The channels after a rescan, sent to the clients when some were added or removed
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelsEvent {
+    pub channels: Vec<ChannelId>,
+    pub added: Vec<ChannelId>,
+    pub removed: Vec<ChannelId>,
+}
+
 #[derive(Debug, Parser)]
 #[clap(
     name = env!("CARGO_PKG_NAME"),
@@ -43,9 +165,30 @@ impl SharedData {
     about = env!("CARGO_PKG_DESCRIPTION"),
 )]
 pub struct CLArguments {
+    // "serial:///dev/ttyUSB0", "tcp://host:4001" or "mock://", a path means a serial port
     #[clap(short = 'p', long = "port_name", default_value = "/dev/ttyUSB0")]
     pub port_name: String,
 
+    // "NAME=URL" of every MRC-1, ex. "target=/dev/ttyUSB0", "port_name" is not used if given
+    #[clap(long = "controller", value_parser = parse_controller)]
+    pub controllers: Vec<(String, String)>,
+
+    #[clap(long = "http_port", default_value = "8080")]
+    pub http_port: u16,
+
+    // JSON file of the detector names and limits, see "channels.example.json"
+    #[clap(short = 'c', long = "config")]
+    pub config: Option<String>,
+
+    // JSON file of the users and their tokens, see "users.example.json"
+    // every request is allowed without it
+    #[clap(long = "auth_file")]
+    pub auth_file: Option<String>,
+
+    // append-only log of the control actions, JSON lines
+    #[clap(long = "audit_file", default_value = "audit.jsonl")]
+    pub audit_file: String,
+
     #[clap(short = 'r', long = "port_rate", default_value = "9600")]
     pub port_rate: u32,
 
@@ -55,19 +198,129 @@ pub struct CLArguments {
     #[clap(short = 'w', long = "waiting_time_ms", default_value = "500")]
     pub waiting_time: u64,
 
+    // fast but safe ramp-down of the emergency switch-off
+    #[clap(long = "emergency_step", default_value = "50")] // 1 -> 0.1 V
+    pub emergency_step: isize,
+
+    #[clap(long = "emergency_waiting_time_ms", default_value = "200")]
+    pub emergency_waiting_time: u64,
+
     #[clap(short = 'm', long = "max_voltage", default_value = "3000")] // 1 -> 0.1 V
     pub max_voltage: isize,
 
+    // lower limits of the detectors in the order of the scan, 0 means "max_voltage"
+    #[clap(long = "channel_max_voltage", value_delimiter = ',')] // 1 -> 0.1 V
+    pub channel_max_voltage: Vec<isize>,
+
+    // allowed ramp speed, "apply_hv_step" every "waiting_time_ms"
+    #[clap(long = "min_slew", default_value = "1")] // 1 -> 0.1 V/s
+    pub min_slew: isize,
+
+    #[clap(long = "max_slew", default_value = "500")] // 1 -> 0.1 V/s
+    pub max_slew: isize,
+
     #[clap(short = 'l', long = "localhost")] // 1 -> 0.1 V
     pub is_localhost: bool,
+
+    // overall time to wait for the "mrc-1>" prompt
+    #[clap(short = 't', long = "reply_timeout_ms", default_value = "1000")]
+    pub reply_timeout: u64,
+
+    #[clap(long = "max_reply_bytes", default_value = "1024")]
+    pub max_reply_size: usize,
+
+    // a lost link is reopened after "reconnect_min_ms", doubled at every attempt
+    #[clap(long = "reconnect_min_ms", default_value = "500")]
+    pub reconnect_min: u64,
+
+    #[clap(long = "reconnect_max_ms", default_value = "30000")]
+    pub reconnect_max: u64,
+
+    // consecutive reply timeouts before the link is considered lost
+    #[clap(long = "max_timeouts", default_value = "5")]
+    pub max_timeouts: u32,
+
+    // pause between two readings of all the channels
+    #[clap(long = "poll_interval_ms", default_value = "100")]
+    pub poll_interval: u64,
+
+    // directory of the CSV records of every poll, no recording if not given
+    #[clap(long = "record_dir")]
+    pub record_dir: Option<String>,
+
+    #[clap(long = "record_rotate_min", default_value = "60")]
+    pub record_rotate: u64,
+
+    #[clap(long = "record_retention_days", default_value = "30")]
+    pub record_retention: u64,
+
+    // older records are reduced to the min/max of every "record_bucket_sec"
+    #[clap(long = "record_downsample_after_hours", default_value = "24")]
+    pub record_downsample_after: u64,
+
+    #[clap(long = "record_bucket_sec", default_value = "10")]
+    pub record_bucket: u64,
+
+    // over-current trip: one value for all the channels or one per channel, 0 disables
+    #[clap(long = "trip_current", value_delimiter = ',')] // 1 -> 1 nA
+    pub trip_current: Vec<isize>,
+
+    #[clap(long = "trip_action", value_enum, default_value = "ramp-down")]
+    pub trip_action: TripAction,
+
+    // consecutive readings above the threshold before tripping
+    #[clap(long = "trip_debounce", default_value = "3")]
+    pub trip_debounce: usize,
+
+    // released when the current falls this percentage below "trip_current"
+    #[clap(long = "trip_hysteresis_percent", default_value = "10")]
+    pub trip_hysteresis: usize,
+
+    // target of the "ramp-down" action
+    #[clap(long = "trip_safe_voltage", default_value = "0")] // 1 -> 0.1 V
+    pub trip_safe_voltage: isize,
+}
+
+impl CLArguments {
+    /// (name, URL) of every controller, "port_name" is the "default" one if none is given
+    pub fn controller_urls(&self) -> Vec<(String, String)> {
+        if self.controllers.is_empty() {
+            vec![(DEFAULT_CONTROLLER.to_string(), self.port_name.clone())]
+        } else {
+            self.controllers.clone()
+        }
+    }
+}
+
+// the name is a part of the channel ids, ex. "target:0:1:2"
+fn parse_controller(s: &str) -> Result<(String, String), String> {
+    let (name, url) = s
+        .split_once('=')
+        .ok_or("use NAME=URL, ex. target=/dev/ttyUSB0")?;
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-');
+    if !is_valid {
+        return Err(format!(
+            "invalid controller name \"{}\", use letters, digits, '_' and '-'",
+            name
+        ));
+    }
+    Ok((name.to_string(), url.to_string()))
 }
 
 // This error is used only for initialize part
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum OperationError {
     ArgumentError,
     OnceLockError,
-    SerialPortError(String),
+    TransportError(TransportError),
+    ProtocolError(ProtocolError),
+    LimitError(LimitError),
+    ConfigError(ConfigError),
+    CredentialsError(CredentialsError),
     Utf8Error(std::string::FromUtf8Error),
     ParseIntError(std::num::ParseIntError),
     PortGetError,
@@ -75,8 +328,120 @@ pub enum OperationError {
     PortIOError,
     DataGetError,
     JSONSerializeError(serde_json::Error),
-    ReadingError,
     SharedDataError,
+    RampInProgress,
+    // the request itself is wrong, nothing is sent to the modules
+    InvalidRequest(String),
+    // the request is valid but not in the current state
+    RcOff,
+    RampStateError(String),
+    ChannelNotFound(String),
+    // the serial link is lost, the command was not sent
+    Disconnected,
+    // the modules are not scanned yet, with the reason
+    Unavailable(String),
+}
+
+impl OperationError {
+    // the reply was broken or did not arrive in time, the link itself may be fine
+    pub fn is_bad_reply(&self) -> bool {
+        matches!(
+            self,
+            OperationError::ProtocolError(_)
+                | OperationError::TransportError(TransportError::Timeout(_))
+                | OperationError::TransportError(TransportError::ReplyTooLong(_))
+        )
+    }
+
+    /// machine readable name of the error, sent with the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            OperationError::InvalidRequest(_) => "invalid_request",
+            OperationError::LimitError(_) => "limit_exceeded",
+            OperationError::RcOff => "rc_off",
+            OperationError::RampInProgress => "ramp_in_progress",
+            OperationError::RampStateError(_) => "ramp_state",
+            OperationError::ChannelNotFound(_) => "channel_not_found",
+            OperationError::Disconnected => "disconnected",
+            OperationError::Unavailable(_) => "hardware_unavailable",
+            OperationError::TransportError(_)
+            | OperationError::PortGetError
+            | OperationError::PortIOError => "serial_error",
+            OperationError::ProtocolError(_)
+            | OperationError::Utf8Error(_)
+            | OperationError::ParseIntError(_)
+            | OperationError::DataGetError => "bad_reply",
+            _ => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            "invalid_request" | "limit_exceeded" => StatusCode::BAD_REQUEST,
+            "channel_not_found" => StatusCode::NOT_FOUND,
+            "rc_off" | "ramp_in_progress" | "ramp_state" => StatusCode::CONFLICT,
+            "serial_error" | "bad_reply" | "disconnected" | "hardware_unavailable" => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// JSON body of every error response, ex. {"code": "rc_off", "message": "..."}
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<&OperationError> for ErrorBody {
+    fn from(err: &OperationError) -> Self {
+        ErrorBody {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+pub fn error_reply(code: &'static str, message: String, status: StatusCode) -> Response {
+    warp::reply::with_status(warp::reply::json(&ErrorBody { code, message }), status)
+        .into_response()
+}
+
+/// the value as JSON, or the error with its status code
+pub fn json_reply<T: Serialize>(result: Result<T, OperationError>) -> Response {
+    match result {
+        Ok(value) => warp::reply::json(&value).into_response(),
+        Err(e) => {
+            log::error!("Rejected: {}", e);
+            error_reply(e.code(), e.to_string(), e.status())
+        }
+    }
+}
+
+/// the rejections of warp are also sent as JSON, except for 404 and 405
+pub async fn handle_rejection(err: Rejection) -> Result<Response, Rejection> {
+    if let Some(e) = err.find::<AuthError>() {
+        Ok(e.reply())
+    } else if let Some(e) = err.find::<OperationError>() {
+        log::error!("Error: {}", e);
+        Ok(error_reply(e.code(), e.to_string(), e.status()))
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        Ok(error_reply(
+            "invalid_request",
+            e.to_string(),
+            StatusCode::BAD_REQUEST,
+        ))
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        Ok(error_reply(
+            "invalid_request",
+            e.to_string(),
+            StatusCode::BAD_REQUEST,
+        ))
+    } else {
+        Err(err)
+    }
 }
 
 impl fmt::Display for OperationError {
@@ -84,7 +449,11 @@ impl fmt::Display for OperationError {
         match *self {
             OperationError::ArgumentError => write!(f, "Could not get Argument variable"),
             OperationError::OnceLockError => write!(f, "OnceLockError"),
-            OperationError::SerialPortError(ref err) => write!(f, "SerialPortError: {}", err),
+            OperationError::TransportError(ref err) => write!(f, "TransportError: {}", err),
+            OperationError::ProtocolError(ref err) => write!(f, "ProtocolError: {}", err),
+            OperationError::LimitError(ref err) => write!(f, "LimitError: {}", err),
+            OperationError::ConfigError(ref err) => write!(f, "ConfigError: {}", err),
+            OperationError::CredentialsError(ref err) => write!(f, "CredentialsError: {}", err),
             OperationError::Utf8Error(ref err) => write!(f, "Utf8 port read Error: {}", err),
             OperationError::ParseIntError(ref err) => write!(f, "Parse Error: {}", err),
             OperationError::PortGetError => write!(f, "Port Get Error"),
@@ -94,8 +463,20 @@ impl fmt::Display for OperationError {
             OperationError::JSONSerializeError(ref err) => {
                 write!(f, "JSON Serialize Error: {}", err)
             }
-            OperationError::ReadingError => write!(f, "SSE Reading Error"),
             OperationError::SharedDataError => write!(f, "Could not get shared data"),
+            OperationError::RampInProgress => write!(f, "Ramp is already running"),
+            OperationError::InvalidRequest(ref msg) => write!(f, "Invalid request: {}", msg),
+            OperationError::RcOff => write!(f, "RC mode is off, turn it on first"),
+            OperationError::RampStateError(ref msg) => write!(f, "{}", msg),
+            OperationError::ChannelNotFound(ref channel) => {
+                write!(f, "Channel {} is not found", channel)
+            }
+            OperationError::Disconnected => {
+                write!(f, "Serial link is lost, the command was not sent")
+            }
+            OperationError::Unavailable(ref reason) => {
+                write!(f, "Hardware is unavailable: {}", reason)
+            }
         }
     }
 }
@@ -103,9 +484,45 @@ impl fmt::Display for OperationError {
 impl Error for OperationError {}
 impl warp::reject::Reject for OperationError {}
 
-impl From<serialport::Error> for OperationError {
-    fn from(err: serialport::Error) -> Self {
-        OperationError::SerialPortError(err.to_string())
+impl From<TransportError> for OperationError {
+    fn from(err: TransportError) -> Self {
+        OperationError::TransportError(err)
+    }
+}
+
+impl From<LinkError> for OperationError {
+    fn from(err: LinkError) -> Self {
+        match err {
+            LinkError::Transport(err) => OperationError::TransportError(err),
+            LinkError::Protocol(err) => OperationError::ProtocolError(err),
+            LinkError::Io(_) => OperationError::PortIOError,
+            LinkError::Disconnected => OperationError::Disconnected,
+            LinkError::Closed => OperationError::PortGetError,
+        }
+    }
+}
+
+impl From<ProtocolError> for OperationError {
+    fn from(err: ProtocolError) -> Self {
+        OperationError::ProtocolError(err)
+    }
+}
+
+impl From<LimitError> for OperationError {
+    fn from(err: LimitError) -> Self {
+        OperationError::LimitError(err)
+    }
+}
+
+impl From<ConfigError> for OperationError {
+    fn from(err: ConfigError) -> Self {
+        OperationError::ConfigError(err)
+    }
+}
+
+impl From<CredentialsError> for OperationError {
+    fn from(err: CredentialsError) -> Self {
+        OperationError::CredentialsError(err)
     }
 }
 
@@ -0,0 +1,265 @@
+use crate::protocol::{
+    BUS_NUM, CH_NUM, DEV_NUM, LINE_END, PROMPT, REG_CURRENT, REG_CURRENT_LIMIT, REG_ONOFF,
+    REG_POLARITY, REG_READBACK, REG_SETPOINT, REG_STATUS,
+};
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+
+pub const ERROR_REPLY: &str = "ERROR!";
+pub const DEFAULT_MODULES: &str = "0:0:27,0:1:17";
+
+/// One simulated MHV4 module, IDC 17 (fixed polarity) or IDC 27 (switchable polarity)
+#[derive(Debug, Clone)]
+pub struct SimModule {
+    pub idc: usize,
+    pub is_rc: bool,
+    setpoint: [isize; CH_NUM],
+    is_on: [bool; CH_NUM],
+    current_limit: [isize; CH_NUM],
+    is_positive: [bool; CH_NUM],
+    leakage: [isize; CH_NUM], // nA drawn at 100 V
+    other: BTreeMap<usize, isize>,
+}
+
+impl SimModule {
+    pub fn new(in_idc: usize) -> SimModule {
+        SimModule {
+            idc: in_idc,
+            is_rc: false,
+            setpoint: [0; CH_NUM],
+            is_on: [false; CH_NUM],
+            current_limit: [20_000; CH_NUM],
+            is_positive: [true; CH_NUM],
+            leakage: [10; CH_NUM],
+            other: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_polarity(&mut self, ch: usize, is_positive: bool) {
+        self.is_positive[ch] = is_positive;
+    }
+
+    pub fn set_leakage(&mut self, ch: usize, nano_ampere: isize) {
+        self.leakage[ch] = nano_ampere;
+    }
+
+    pub fn get_setpoint(&self, ch: usize) -> isize {
+        self.setpoint[ch]
+    }
+
+    pub fn get_onoff(&self, ch: usize) -> bool {
+        self.is_on[ch]
+    }
+
+    // the output follows the setpoint immediately, there is no ramp model
+    fn voltage(&self, ch: usize) -> isize {
+        if self.is_on[ch] {
+            self.setpoint[ch]
+        } else {
+            0
+        }
+    }
+
+    fn current(&self, ch: usize) -> isize {
+        self.voltage(ch) * self.leakage[ch] / 1000
+    }
+
+    pub fn read_register(&self, reg: usize) -> Option<isize> {
+        let value = match reg {
+            0..=3 => self.setpoint[reg - REG_SETPOINT],
+            4..=7 => self.is_on[reg - REG_ONOFF] as isize,
+            8..=11 => self.current_limit[reg - REG_CURRENT_LIMIT],
+            32..=35 => self.voltage(reg - REG_READBACK),
+            36..=39 => self.is_on[reg - REG_STATUS] as isize,
+            46..=49 => self.is_positive[reg - REG_POLARITY] as isize,
+            50..=53 => self.current(reg - REG_CURRENT),
+            _ => *self.other.get(&reg)?,
+        };
+        Some(value)
+    }
+
+    pub fn write_register(&mut self, reg: usize, value: isize) -> Option<isize> {
+        match reg {
+            0..=3 => self.setpoint[reg - REG_SETPOINT] = value,
+            4..=7 => self.is_on[reg - REG_ONOFF] = value != 0,
+            8..=11 => self.current_limit[reg - REG_CURRENT_LIMIT] = value,
+            // read only registers
+            32..=39 | 50..=53 => return None,
+            // the polarity of IDC 17 module is fixed by the hardware
+            46..=49 if self.idc == 17 => return None,
+            46..=49 => self.is_positive[reg - REG_POLARITY] = value != 0,
+            _ => {
+                self.other.insert(reg, value);
+            }
+        }
+        Some(value)
+    }
+}
+
+/// Software model of an MRC-1 controller, speaking the same text protocol
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    buses: Vec<Vec<Option<SimModule>>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator {
+            buses: vec![vec![None; DEV_NUM]; BUS_NUM],
+        }
+    }
+
+    /// comma separated "BUS:DEV:IDC" list, ex. "0:0:27,0:1:17"
+    pub fn from_spec(spec: &str) -> Result<Simulator, String> {
+        let mut sim = Simulator::new();
+        for module in spec.split(',').filter(|s| !s.is_empty()) {
+            let (bus, dev, idc) = parse_module_spec(module)?;
+            sim.add_module(bus, dev, SimModule::new(idc));
+        }
+        Ok(sim)
+    }
+
+    pub fn add_module(&mut self, bus: usize, dev: usize, module: SimModule) {
+        self.buses[bus][dev] = Some(module);
+    }
+
+    pub fn remove_module(&mut self, bus: usize, dev: usize) -> Option<SimModule> {
+        self.buses[bus][dev].take()
+    }
+
+    pub fn module(&self, bus: usize, dev: usize) -> Option<&SimModule> {
+        self.buses.get(bus)?.get(dev)?.as_ref()
+    }
+
+    pub fn module_mut(&mut self, bus: usize, dev: usize) -> Option<&mut SimModule> {
+        self.buses.get_mut(bus)?.get_mut(dev)?.as_mut()
+    }
+
+    pub fn set_rc_all(&mut self, is_rc: bool) {
+        for module in self.buses.iter_mut().flatten().flatten() {
+            module.is_rc = is_rc;
+        }
+    }
+
+    /// returns the full reply of one command line: echo, reply lines and the prompt
+    pub fn execute(&mut self, line: &str) -> String {
+        let command = line.trim();
+        let lines = self
+            .reply_lines(command)
+            .unwrap_or_else(|| vec![ERROR_REPLY.to_string()]);
+
+        let mut reply = String::from(command);
+        reply.push_str(LINE_END);
+        for line in lines {
+            reply.push_str(&line);
+            reply.push_str(LINE_END);
+        }
+        reply.push_str(PROMPT);
+        reply
+    }
+
+    fn reply_lines(&mut self, command: &str) -> Option<Vec<String>> {
+        let tokens = command.split_whitespace().collect::<Vec<_>>();
+        let mut nums: Vec<isize> = Vec::new();
+        for token in tokens.iter().skip(1) {
+            nums.push(token.parse().ok()?);
+        }
+
+        match (tokens.first().copied(), nums.as_slice()) {
+            (None, _) => Some(Vec::new()),
+            (Some("sc"), &[bus]) => {
+                let modules = self.buses.get(usize::try_from(bus).ok()?)?;
+                let mut lines = vec![format!("ID-SCAN BUS {}:", bus)];
+                for (dev, module) in modules.iter().enumerate() {
+                    match module {
+                        Some(m) => {
+                            let power = if m.is_rc { "ON" } else { "OFF" };
+                            lines.push(format!("{}: {}, {}", dev, m.idc, power));
+                        }
+                        None => lines.push(format!("{}: -", dev)),
+                    }
+                }
+                Some(lines)
+            }
+            (Some("re"), &[bus, dev, reg]) => {
+                let value = self
+                    .find_module(bus, dev)?
+                    .read_register(usize::try_from(reg).ok()?)?;
+                Some(vec![format!("RE {} {} {} {}", bus, dev, reg, value)])
+            }
+            (Some("se"), &[bus, dev, reg, value]) => {
+                self.find_module(bus, dev)?
+                    .write_register(usize::try_from(reg).ok()?, value)?;
+                Some(vec![format!("SE {} {} {} {}", bus, dev, reg, value)])
+            }
+            (Some("on"), &[bus, dev]) => {
+                self.find_module(bus, dev)?.is_rc = true;
+                Some(vec![format!("ON {} {}", bus, dev)])
+            }
+            (Some("off"), &[bus, dev]) => {
+                self.find_module(bus, dev)?.is_rc = false;
+                Some(vec![format!("OFF {} {}", bus, dev)])
+            }
+            _ => None,
+        }
+    }
+
+    fn find_module(&mut self, bus: isize, dev: isize) -> Option<&mut SimModule> {
+        self.module_mut(usize::try_from(bus).ok()?, usize::try_from(dev).ok()?)
+    }
+}
+
+pub fn parse_module_spec(s: &str) -> Result<(usize, usize, usize), String> {
+    let nums = s
+        .split(':')
+        .map(|x| x.trim().parse::<usize>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match nums.as_slice() {
+        &[bus, dev, idc] if bus < BUS_NUM && dev < DEV_NUM && (idc == 17 || idc == 27) => {
+            Ok((bus, dev, idc))
+        }
+        _ => Err(format!("invalid module \"{}\", use BUS:DEV:IDC", s)),
+    }
+}
+
+/// Answers the commands arriving on the port until it is closed (e.g. the master side of a pty)
+pub fn serve<P: Read + Write + ?Sized>(sim: &mut Simulator, port: &mut P) -> std::io::Result<()> {
+    let mut line: Vec<u8> = Vec::new();
+    let mut buf: Vec<u8> = vec![0; 64];
+    loop {
+        let size = match port.read(buf.as_mut_slice()) {
+            Ok(0) => return Ok(()),
+            Ok(size) => size,
+            Err(ref e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+
+        for &byte in &buf[..size] {
+            match byte {
+                b'\r' => {
+                    let command = String::from_utf8_lossy(&line).to_string();
+                    line.clear();
+                    let reply = sim.execute(&command);
+                    log::trace!("simulator: {:?} -> {:?}", command, reply);
+                    port.write_all(reply.as_bytes())?;
+                    port.flush()?;
+                }
+                b'\n' => {}
+                _ => line.push(byte),
+            }
+        }
+    }
+}
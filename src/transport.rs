@@ -0,0 +1,292 @@
+use crate::protocol::PROMPT;
+use crate::simulator::{self, Simulator};
+use serialport::{ClearBuffer, SerialPort};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Byte stream to the MRC-1, the reads time out like a serial port
+pub trait Transport: Read + Write + Send {
+    fn description(&self) -> String;
+
+    /// drops the bytes already received, ex. a late reply of the previous command
+    fn clear_input(&mut self) -> std::io::Result<()>;
+}
+
+/// Accumulates the reply until the prompt arrives
+pub fn read_until_prompt<R: Read + ?Sized>(
+    port: &mut R,
+    timeout: Duration,
+    max_len: usize,
+) -> Result<String, TransportError> {
+    let deadline = Instant::now() + timeout;
+    let mut reply: Vec<u8> = Vec::new();
+    let mut buf: Vec<u8> = vec![0; 256];
+    loop {
+        match port.read(buf.as_mut_slice()) {
+            Ok(0) => return Err(TransportError::Io(ErrorKind::UnexpectedEof.into())),
+            Ok(size) => {
+                reply.extend_from_slice(&buf[..size]);
+                if reply.trim_ascii_end().ends_with(PROMPT.as_bytes()) {
+                    return Ok(String::from_utf8_lossy(&reply).to_string());
+                }
+                if reply.len() > max_len {
+                    return Err(TransportError::ReplyTooLong(reply.len()));
+                }
+            }
+            Err(ref e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => (),
+            Err(e) => return Err(TransportError::Io(e)),
+        }
+        if Instant::now() >= deadline {
+            return Err(TransportError::Timeout(
+                String::from_utf8_lossy(&reply).to_string(),
+            ));
+        }
+    }
+}
+
+/// Where the MRC-1 is connected, given as URL in the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortUrl {
+    Serial(String),
+    Tcp(String),
+    Mock(String),
+}
+
+impl PortUrl {
+    /// "serial:///dev/ttyUSB0", "tcp://host:4001", "mock://0:0:27,0:1:17",
+    /// a path without scheme is a local serial port
+    pub fn parse(url: &str) -> Result<PortUrl, TransportError> {
+        match url.split_once("://") {
+            Some(("serial", path)) if !path.is_empty() => Ok(PortUrl::Serial(path.to_string())),
+            Some(("tcp", address)) if !address.is_empty() => Ok(PortUrl::Tcp(address.to_string())),
+            Some(("mock", modules)) => Ok(PortUrl::Mock(modules.to_string())),
+            Some(_) => Err(TransportError::InvalidUrl(url.to_string())),
+            None => Ok(PortUrl::Serial(url.to_string())),
+        }
+    }
+}
+
+pub fn open(
+    url: &str,
+    baud_rate: u32,
+    timeout: Duration,
+) -> Result<Box<dyn Transport>, TransportError> {
+    match PortUrl::parse(url)? {
+        PortUrl::Serial(path) => {
+            let port = serialport::new(&path, baud_rate)
+                .stop_bits(serialport::StopBits::One)
+                .data_bits(serialport::DataBits::Eight)
+                .parity(serialport::Parity::None)
+                .timeout(timeout)
+                .open()?;
+            Ok(Box::new(SerialTransport { port }))
+        }
+        PortUrl::Tcp(address) => {
+            let addr = address
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| TransportError::InvalidUrl(url.to_string()))?;
+            let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(TcpTransport { stream, address }))
+        }
+        PortUrl::Mock(modules) => {
+            let spec = if modules.is_empty() {
+                simulator::DEFAULT_MODULES
+            } else {
+                &modules
+            };
+            let sim = Simulator::from_spec(spec).map_err(TransportError::InvalidUrl)?;
+            Ok(Box::new(MockTransport::new(sim)))
+        }
+    }
+}
+
+/// Local serial port, ex. USB-serial adapter
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+}
+
+impl Transport for SerialTransport {
+    fn description(&self) -> String {
+        format!("serial://{}", self.port.name().unwrap_or_default())
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        Ok(self.port.clear(ClearBuffer::Input)?)
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+/// Raw TCP socket, ex. MRC-1 behind ser2net or a terminal server
+pub struct TcpTransport {
+    stream: TcpStream,
+    address: String,
+}
+
+impl Transport for TcpTransport {
+    fn description(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf: Vec<u8> = vec![0; 256];
+        let result = loop {
+            match self.stream.read(buf.as_mut_slice()) {
+                Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.stream.read(buf) {
+            // same error as a serial port when nothing arrives
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "Operation timed out",
+            )),
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// In-memory simulator, the reply is ready as soon as the command line is written
+pub struct MockTransport {
+    sim: Simulator,
+    line: Vec<u8>,
+    output: VecDeque<u8>,
+}
+
+impl MockTransport {
+    pub fn new(in_sim: Simulator) -> MockTransport {
+        MockTransport {
+            sim: in_sim,
+            line: Vec::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn simulator(&mut self) -> &mut Simulator {
+        &mut self.sim
+    }
+}
+
+impl Transport for MockTransport {
+    fn description(&self) -> String {
+        String::from("mock://")
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        self.output.clear();
+        Ok(())
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.output.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+        self.output.read(buf)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            match byte {
+                b'\r' => {
+                    let command = String::from_utf8_lossy(&self.line).to_string();
+                    self.line.clear();
+                    self.output.extend(self.sim.execute(&command).bytes());
+                }
+                b'\n' => {}
+                _ => self.line.push(byte),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    InvalidUrl(String),
+    Serial(serialport::Error),
+    Io(std::io::Error),
+    Timeout(String),
+    ReplyTooLong(usize),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransportError::InvalidUrl(ref url) => write!(f, "Invalid port URL: {}", url),
+            TransportError::Serial(ref err) => write!(f, "Serial port Error: {}", err),
+            TransportError::Io(ref err) => write!(f, "Connection Error: {}", err),
+            TransportError::Timeout(ref partial) => {
+                write!(f, "No prompt before the timeout, received {:?}", partial)
+            }
+            TransportError::ReplyTooLong(size) => write!(f, "Reply is too long: {} bytes", size),
+        }
+    }
+}
+
+impl Error for TransportError {}
+
+impl From<serialport::Error> for TransportError {
+    fn from(err: serialport::Error) -> Self {
+        TransportError::Serial(err)
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(err: std::io::Error) -> Self {
+        TransportError::Io(err)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::channel::ChannelId;
+use crate::now_ms;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+// number of the events kept for the clients
+const HISTORY_SIZE: usize = 100;
+
+/// What the server does when a channel draws too much current
+#[derive(clap::ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TripAction {
+    /// only report the trip
+    Log,
+    /// pause the running ramp
+    Hold,
+    /// ramp the channel down to the safe voltage
+    RampDown,
+    /// switch the channel off
+    Off,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TripKind {
+    Tripped,
+    Released,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TripEvent {
+    pub kind: TripKind,
+    pub index: usize,
+    pub current: isize,
+    pub threshold: isize,
+    pub action: TripAction,
+    pub time_ms: u64,
+}
+
+/// Sent to the clients as it is
+#[derive(Serialize, Debug, Clone)]
+pub struct TripStatus {
+    pub action: TripAction,
+    pub thresholds: Vec<Option<isize>>,
+    pub tripped: Vec<bool>,
+    pub events: Vec<TripEvent>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    count: usize,
+    is_tripped: bool,
+}
+
+/// Per-channel over-current detection with debounce and hysteresis
+///
+/// A channel trips after "debounce" consecutive readings above its threshold,
+/// and is released (armed again) once the reading falls "hysteresis" percent below the threshold.
+#[derive(Debug, Clone)]
+pub struct TripDetector {
+    thresholds: Vec<Option<isize>>,
+    action: TripAction,
+    debounce: usize,
+    hysteresis: usize,
+    states: Vec<ChannelState>,
+    history: VecDeque<TripEvent>,
+}
+
+impl TripDetector {
+    pub fn new(
+        in_thresholds: Vec<Option<isize>>,
+        in_action: TripAction,
+        in_debounce: usize,
+        in_hysteresis: usize,
+    ) -> TripDetector {
+        let states = vec![ChannelState::default(); in_thresholds.len()];
+        TripDetector {
+            thresholds: in_thresholds,
+            action: in_action,
+            debounce: in_debounce.max(1),
+            hysteresis: in_hysteresis.min(100),
+            states,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn action(&self) -> TripAction {
+        self.action
+    }
+
+    pub fn thresholds(&self) -> &[Option<isize>] {
+        &self.thresholds
+    }
+
+    pub fn is_tripped(&self, index: usize) -> bool {
+        self.states.get(index).is_some_and(|x| x.is_tripped)
+    }
+
+    pub fn tripped(&self) -> Vec<bool> {
+        self.states.iter().map(|x| x.is_tripped).collect()
+    }
+
+    /// the latest events, oldest first
+    pub fn history(&self) -> Vec<TripEvent> {
+        self.history.iter().cloned().collect()
+    }
+
+    pub fn status(&self) -> TripStatus {
+        TripStatus {
+            action: self.action,
+            thresholds: self.thresholds.clone(),
+            tripped: self.tripped(),
+            events: self.history(),
+        }
+    }
+
+    /// Takes the states and the events of "previous" for the channels which are still found,
+    /// "previous_channels" and "channels" are the channels of the detectors in their order
+    ///
+    /// A tripped channel stays tripped after a rescan, the events of the removed channels are dropped.
+    pub fn keep_state(
+        &mut self,
+        previous: &TripDetector,
+        previous_channels: &[ChannelId],
+        channels: &[ChannelId],
+    ) {
+        let new_index = |index: usize| {
+            previous_channels
+                .get(index)
+                .and_then(|id| channels.iter().position(|x| x == id))
+        };
+        for (index, state) in previous.states.iter().enumerate() {
+            if let Some(new_state) = new_index(index).and_then(|x| self.states.get_mut(x)) {
+                *new_state = *state;
+            }
+        }
+        self.history = previous
+            .history
+            .iter()
+            .filter_map(|event| {
+                new_index(event.index).map(|index| TripEvent {
+                    index,
+                    ..event.clone()
+                })
+            })
+            .collect();
+    }
+
+    /// one reading of all the channels, None when the value could not be read
+    pub fn check(&mut self, currents: &[Option<isize>]) -> Vec<TripEvent> {
+        let mut events: Vec<TripEvent> = Vec::new();
+        for (index, (state, &threshold)) in self
+            .states
+            .iter_mut()
+            .zip(self.thresholds.iter())
+            .enumerate()
+        {
+            let (Some(threshold), Some(Some(current))) = (threshold, currents.get(index)) else {
+                continue;
+            };
+            // the sign follows the polarity of the module
+            let current = *current;
+            let magnitude = current.abs();
+
+            let kind = if state.is_tripped {
+                if magnitude * 100 >= threshold * (100 - self.hysteresis as isize) {
+                    continue;
+                }
+                state.is_tripped = false;
+                state.count = 0;
+                TripKind::Released
+            } else {
+                if magnitude <= threshold {
+                    state.count = 0;
+                    continue;
+                }
+                state.count += 1;
+                if state.count < self.debounce {
+                    continue;
+                }
+                state.is_tripped = true;
+                TripKind::Tripped
+            };
+            events.push(TripEvent {
+                kind,
+                index,
+                current,
+                threshold,
+                action: self.action,
+                time_ms: now_ms(),
+            });
+        }
+
+        for event in events.iter() {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(event.clone());
+        }
+        events
+    }
+}
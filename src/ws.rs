@@ -0,0 +1,186 @@
+use crate::auth;
+use crate::shared::{ChannelsEvent, ErrorBody, MonitorValue, OperationError, StateEvent};
+use crate::{control, ControlRequest, CHANNEL_EVENTS, DATA, MONITOR, STATE_EVENTS, TRIP_EVENTS};
+use futures::{SinkExt, StreamExt};
+use mhv4_monitor::credentials::Role;
+use mhv4_monitor::trip::TripEvent;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection, Reply};
+
+/// Sent to the client, tagged by "type"
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    /// the same snapshot as "/sse"
+    Monitor(MonitorValue),
+    /// RC mode, ON/OFF or setpoints are changed by any client or by the server
+    State(StateEvent),
+    Trip(TripEvent),
+    /// the channels are added or removed by a rescan
+    Channels(ChannelsEvent),
+    /// reply to a control message with the same "id"
+    Result {
+        id: Option<u64>,
+        ok: bool,
+        error: Option<ErrorBody>,
+    },
+}
+
+/// Control message of the client, ex. {"id": 1, "action": "onoff", "value": [true, false]}
+///
+/// The actions are "status", "onoff", "apply", "ramp", "emergency" and "rescan", with the
+/// body of the HTTP route as "value".
+#[derive(Deserialize, Debug)]
+pub struct ClientMessage {
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub request: ControlRequest,
+}
+
+/// "/ws", the token is given by "?token=" for the browsers
+pub fn route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("ws")
+        .and(warp::ws())
+        .and(auth::with_role(Role::Observer))
+        .and(warp::addr::remote())
+        .map(|ws: Ws, user: String, address: Option<SocketAddr>| {
+            ws.on_upgrade(move |socket| client(socket, user, address))
+        })
+}
+
+async fn client(socket: WebSocket, user: String, address: Option<SocketAddr>) {
+    log::info!("WebSocket client {} is connected", user);
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let mut monitor = subscribe(&MONITOR);
+    let mut trip = subscribe(&TRIP_EVENTS);
+    let mut state = subscribe(&STATE_EVENTS);
+    let mut channels = subscribe(&CHANNEL_EVENTS);
+    // the control actions run in their own tasks, the telemetry is not stopped
+    let (result_tx, mut result_rx) = mpsc::unbounded_channel::<ServerMessage>();
+
+    // the current state first
+    let first = DATA
+        .get()
+        .and_then(|x| x.lock().ok())
+        .map(|x| ServerMessage::State(x.state()));
+    if let Some(first) = first {
+        if send(&mut ws_tx, &first).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let message = tokio::select! {
+            received = ws_rx.next() => match received {
+                Some(Ok(received)) if received.is_close() => break,
+                Some(Ok(received)) => {
+                    if let Ok(text) = received.to_str() {
+                        run_control(text, &user, address, result_tx.clone());
+                    }
+                    continue;
+                }
+                Some(Err(e)) => {
+                    log::debug!("WebSocket error: {}", e);
+                    break;
+                }
+                None => break,
+            },
+            Some(value) = recv(&mut monitor) => ServerMessage::Monitor(value),
+            Some(event) = recv(&mut trip) => ServerMessage::Trip(event),
+            Some(event) = recv(&mut state) => ServerMessage::State(event),
+            Some(event) = recv(&mut channels) => ServerMessage::Channels(event),
+            Some(result) = result_rx.recv() => result,
+        };
+        if send(&mut ws_tx, &message).await.is_err() {
+            break;
+        }
+    }
+    let _ = ws_tx.close().await;
+    log::info!("WebSocket client {} is disconnected", user);
+}
+
+fn run_control(
+    text: &str,
+    user: &str,
+    address: Option<SocketAddr>,
+    result_tx: mpsc::UnboundedSender<ServerMessage>,
+) {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = OperationError::InvalidRequest(e.to_string());
+            let _ = result_tx.send(result_message(None, Err(error)));
+            return;
+        }
+    };
+    if !auth::has_role(user, Role::Operator) {
+        log::warn!("Rejected: \"{}\" is not an operator", user);
+        let _ = result_tx.send(ServerMessage::Result {
+            id: message.id,
+            ok: false,
+            error: Some(ErrorBody {
+                code: "forbidden",
+                message: String::from("Operator role required"),
+            }),
+        });
+        return;
+    }
+
+    let user = user.to_string();
+    tokio::spawn(async move {
+        let result = control(&user, address, message.request).await;
+        if let Err(ref e) = result {
+            log::error!("Rejected: {}", e);
+        }
+        let _ = result_tx.send(result_message(message.id, result));
+    });
+}
+
+fn result_message(id: Option<u64>, result: Result<bool, OperationError>) -> ServerMessage {
+    match result {
+        Ok(ok) => ServerMessage::Result {
+            id,
+            ok,
+            error: None,
+        },
+        Err(ref e) => ServerMessage::Result {
+            id,
+            ok: false,
+            error: Some(ErrorBody::from(e)),
+        },
+    }
+}
+
+fn subscribe<T: Clone>(
+    tx: &std::sync::OnceLock<broadcast::Sender<T>>,
+) -> Option<broadcast::Receiver<T>> {
+    tx.get().map(|x| x.subscribe())
+}
+
+// pending forever if there is no channel, so "select!" skips it
+async fn recv<T: Clone>(rx: &mut Option<broadcast::Receiver<T>>) -> Option<T> {
+    let rx = match rx {
+        Some(rx) => rx,
+        None => return std::future::pending().await,
+    };
+    loop {
+        match rx.recv().await {
+            Ok(value) => return Some(value),
+            // slow client, skip to the latest one
+            Err(RecvError::Lagged(num)) => log::debug!("WebSocket client skipped {} messages", num),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn send<S>(ws_tx: &mut S, message: &ServerMessage) -> Result<(), ()>
+where
+    S: SinkExt<Message> + Unpin,
+{
+    let json = serde_json::to_string(message).map_err(|_| ())?;
+    ws_tx.send(Message::text(json)).await.map_err(|_| ())
+}
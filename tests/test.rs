@@ -1,9 +1,39 @@
+use mhv4_monitor::audit::{self, AuditEntry, AuditFilter, AuditLog, AuditWriter};
+use mhv4_monitor::channel::{ChannelId, DEFAULT_CONTROLLER};
+use mhv4_monitor::client;
+use mhv4_monitor::config::{Config, ConfigError, ScannedChannel};
+use mhv4_monitor::credentials::{Credentials, CredentialsError, Role};
+use mhv4_monitor::history::{self, Downsampling};
+use mhv4_monitor::limits::{LimitError, VoltageLimits};
+use mhv4_monitor::link::{LinkError, LinkState, Priority, Reconnect, SerialHandle};
+use mhv4_monitor::protocol::{self, Command, ProtocolError, Response, ScanEntry};
+use mhv4_monitor::ramping::{self, ChannelProgress, RampPlan, RampState, RampStatus, RampWrite};
+use mhv4_monitor::recorder::{self, Record, Recorder, RecorderConfig};
+use mhv4_monitor::simulator::{self, SimModule, Simulator};
+use mhv4_monitor::transport::{self, PortUrl, Transport, TransportError};
+use mhv4_monitor::trip::{TripAction, TripDetector, TripKind};
+use serialport::{SerialPort, TTYPort};
 use std::error::Error;
 use std::io::prelude::*;
+use std::net::TcpListener;
+use std::sync::{mpsc, Mutex};
 use std::time::Duration;
+use tokio::sync::Notify;
 
-fn mrc_scan_result(bus: isize) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut port = serialport::new("/dev/ttyUSB0", 9600)
+// start the simulator on a pseudo-terminal and return the pty name
+fn start_simulator(sim: Simulator) -> String {
+    let (mut master, slave) = TTYPort::pair().expect("Cannot open the pty");
+    let name = slave.name().expect("Cannot get the pty name");
+    std::thread::spawn(move || {
+        let _slave = slave;
+        let mut sim = sim;
+        let _ = simulator::serve(&mut sim, &mut master);
+    });
+    name
+}
+
+fn mrc_command_result(port_name: &str, command: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut port = serialport::new(port_name, 9600)
         .stop_bits(serialport::StopBits::One)
         .data_bits(serialport::DataBits::Eight)
         .parity(serialport::Parity::None)
@@ -14,8 +44,8 @@ fn mrc_scan_result(bus: isize) -> Result<Vec<String>, Box<dyn Error>> {
     let mut vec: Vec<String> = Vec::new();
 
     // write to the serial port
-    let command = format!("sc {}\r", bus);
-    match port.write(command.as_bytes()) {
+    let command = format!("{}\r", command);
+    match port.write_all(command.as_bytes()) {
         Ok(_) => std::io::stdout().flush()?,
         Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
         Err(e) => eprintln!("{:?}", e),
@@ -29,29 +59,1077 @@ fn mrc_scan_result(bus: isize) -> Result<Vec<String>, Box<dyn Error>> {
             let string = String::from_utf8(bytes.to_vec())?;
             let v = string.split("\n\r").collect::<Vec<_>>();
             vec = v.iter().map(|&s| s.to_string()).collect();
-            println!("Scan result:");
+            println!("Result:");
             println!("{:?}", vec);
         }
         Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
         Err(e) => eprintln!("{:?}", e),
     }
-    return Ok(vec);
+    Ok(vec)
 }
 
 #[test]
 fn scan_test() {
-    let sc0_result_vec = mrc_scan_result(0).expect("Cannot get the output");
-    let sc1_result_vec = mrc_scan_result(1).expect("Cannot get the output");
+    let mut sim = Simulator::new();
+    sim.add_module(0, 0, SimModule::new(27));
+    sim.add_module(1, 3, SimModule::new(17));
+    let port_name = start_simulator(sim);
+
+    let sc0_result_vec = mrc_command_result(&port_name, "sc 0").expect("Cannot get the output");
+    let sc1_result_vec = mrc_command_result(&port_name, "sc 1").expect("Cannot get the output");
 
     // result of sc 0
     assert_eq!(sc0_result_vec.len(), 19);
     assert_eq!(sc0_result_vec[0], String::from("sc 0"));
     assert_eq!(sc0_result_vec[1], String::from("ID-SCAN BUS 0:"));
+    assert_eq!(sc0_result_vec[2], String::from("0: 27, OFF"));
     assert_eq!(sc0_result_vec[18], String::from("mrc-1>"));
 
     // result of sc 1
     assert_eq!(sc1_result_vec.len(), 19);
     assert_eq!(sc1_result_vec[0], String::from("sc 1"));
     assert_eq!(sc1_result_vec[1], String::from("ID-SCAN BUS 1:"));
+    assert_eq!(sc1_result_vec[5], String::from("3: 17, OFF"));
     assert_eq!(sc1_result_vec[18], String::from("mrc-1>"));
 }
+
+#[test]
+fn register_test() {
+    let mut sim = Simulator::new();
+    sim.add_module(0, 1, SimModule::new(17));
+    let port_name = start_simulator(sim);
+
+    // set the voltage and switch on the channel 2
+    let result = mrc_command_result(&port_name, "se 0 1 2 1000").expect("Cannot get the output");
+    assert_eq!(result, vec!["se 0 1 2 1000", "SE 0 1 2 1000", "mrc-1>"]);
+    mrc_command_result(&port_name, "se 0 1 6 1").expect("Cannot get the output");
+
+    let result = mrc_command_result(&port_name, "re 0 1 34").expect("Cannot get the output");
+    assert_eq!(result, vec!["re 0 1 34", "RE 0 1 34 1000", "mrc-1>"]);
+    let result = mrc_command_result(&port_name, "re 0 1 38").expect("Cannot get the output");
+    assert_eq!(result[1], String::from("RE 0 1 38 1"));
+
+    // polarity of IDC 17 is fixed, and no module at dev 2
+    let result = mrc_command_result(&port_name, "se 0 1 46 0").expect("Cannot get the output");
+    assert_eq!(result[1], String::from("ERROR!"));
+    let result = mrc_command_result(&port_name, "re 0 2 32").expect("Cannot get the output");
+    assert_eq!(result[1], String::from("ERROR!"));
+}
+
+#[test]
+fn port_url_test() {
+    assert_eq!(
+        PortUrl::parse("/dev/ttyUSB0").unwrap(),
+        PortUrl::Serial(String::from("/dev/ttyUSB0"))
+    );
+    assert_eq!(
+        PortUrl::parse("serial:///dev/ttyUSB1").unwrap(),
+        PortUrl::Serial(String::from("/dev/ttyUSB1"))
+    );
+    assert_eq!(
+        PortUrl::parse("tcp://192.168.1.10:4001").unwrap(),
+        PortUrl::Tcp(String::from("192.168.1.10:4001"))
+    );
+    assert_eq!(
+        PortUrl::parse("mock://").unwrap(),
+        PortUrl::Mock(String::new())
+    );
+    assert!(PortUrl::parse("udp://host:4001").is_err());
+    assert!(PortUrl::parse("tcp://").is_err());
+}
+
+#[test]
+fn transport_test() {
+    // serve the simulator over TCP like ser2net
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot bind");
+    let address = listener.local_addr().expect("Cannot get the address");
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Cannot accept");
+        let mut sim = Simulator::from_spec("1:2:27").expect("Invalid spec");
+        let _ = simulator::serve(&mut sim, &mut stream);
+    });
+
+    for url in [format!("tcp://{}", address), String::from("mock://1:2:27")] {
+        let mut port =
+            transport::open(&url, 9600, Duration::from_millis(100)).expect("Cannot open");
+        port.write_all(b"re 1 2 46\r").expect("Cannot write");
+        std::thread::sleep(Duration::from_millis(50));
+
+        let mut buf: Vec<u8> = vec![0; 100];
+        let size = port.read(buf.as_mut_slice()).expect("Cannot read");
+        let string = String::from_utf8(buf[..size].to_vec()).expect("Invalid UTF-8");
+        assert_eq!(string, "re 1 2 46\n\rRE 1 2 46 1\n\rmrc-1>", "{}", url);
+    }
+}
+
+#[test]
+fn protocol_test() {
+    let read = Command::Read {
+        bus: 0,
+        dev: 1,
+        reg: 32,
+    };
+    assert_eq!(read.to_wire(), "re 0 1 32\r");
+    assert_eq!(
+        protocol::parse_response(&read, "re 0 1 32\n\rRE 0 1 32 1234\n\rmrc-1>"),
+        Ok(Response::Value(1234))
+    );
+    assert_eq!(
+        protocol::parse_response(&read, "re 0 1 32\n\rERROR!\n\rmrc-1>"),
+        Err(ProtocolError::ErrorReply(String::from("ERROR!")))
+    );
+    assert_eq!(
+        protocol::parse_response(&read, "re 0 1 32\n\rRE 0 1 32 12"),
+        Err(ProtocolError::MissingPrompt)
+    );
+    assert!(matches!(
+        protocol::parse_response(&read, "re 0 1 33\n\rRE 0 1 33 1234\n\rmrc-1>"),
+        Err(ProtocolError::EchoMismatch { .. })
+    ));
+    assert!(matches!(
+        protocol::parse_response(&read, "re 0 1 32\n\rRE 0 1 32 12x4\n\rmrc-1>"),
+        Err(ProtocolError::Malformed(_))
+    ));
+
+    // the simulator speaks the same protocol
+    let mut sim = Simulator::from_spec("1:3:17,1:15:27").expect("Invalid spec");
+    let scan = Command::Scan { bus: 1 };
+    assert_eq!(
+        protocol::parse_response(&scan, &sim.execute(&scan.to_string())),
+        Ok(Response::Scan(vec![
+            ScanEntry {
+                dev: 3,
+                idc: 17,
+                is_on: false
+            },
+            ScanEntry {
+                dev: 15,
+                idc: 27,
+                is_on: false
+            },
+        ]))
+    );
+    let on = Command::On { bus: 1, dev: 3 };
+    assert_eq!(
+        protocol::parse_response(&on, &sim.execute(&on.to_string())),
+        Ok(Response::Done)
+    );
+    let off = Command::Off { bus: 1, dev: 4 };
+    assert!(protocol::parse_response(&off, &sim.execute(&off.to_string())).is_err());
+
+    // the command lines given by the users
+    for command in [scan, read, on, off] {
+        assert_eq!(command.to_string().parse(), Ok(command));
+    }
+    assert_eq!(
+        " se 0 1 2  -5 ".parse(),
+        Ok(Command::Set {
+            bus: 0,
+            dev: 1,
+            reg: 2,
+            value: -5
+        })
+    );
+    for line in [
+        "",
+        "sc",
+        "sc 2",
+        "re 0 16 32",
+        "se 0 1 0",
+        "on 0 1 2",
+        "reset 0",
+    ] {
+        assert!(line.parse::<Command>().is_err(), "{}", line);
+    }
+    assert_eq!(
+        serde_json::to_string(&Response::Value(1234)).unwrap(),
+        r#"{"value":1234}"#
+    );
+}
+
+// delivers the reply in pieces, "None" is a read timeout like a slow serial line
+struct ChunkReader(Vec<Option<&'static str>>);
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.0.is_empty() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        match self.0.remove(0) {
+            Some(chunk) => {
+                buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+                Ok(chunk.len())
+            }
+            None => Err(std::io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+#[test]
+fn framing_test() {
+    let timeout = Duration::from_millis(100);
+    let mut reader = ChunkReader(vec![
+        Some("re 0 1 32\n\rRE 0"),
+        None,
+        Some(" 1 32 1234\n\rmrc"),
+        Some("-1>"),
+        Some("next reply"),
+    ]);
+    let reply = transport::read_until_prompt(&mut reader, timeout, 1024).expect("No reply");
+    assert_eq!(reply, "re 0 1 32\n\rRE 0 1 32 1234\n\rmrc-1>");
+
+    let mut reader = ChunkReader(vec![Some("re 0 1 32\n\rRE 0 1 32 1234\n\r")]);
+    assert!(matches!(
+        transport::read_until_prompt(&mut reader, timeout, 1024),
+        Err(TransportError::Timeout(_))
+    ));
+
+    let mut reader = ChunkReader(vec![Some("sc 0\n\rID-SCAN BUS 0:\n\r"); 10]);
+    assert!(matches!(
+        transport::read_until_prompt(&mut reader, timeout, 64),
+        Err(TransportError::ReplyTooLong(_))
+    ));
+}
+
+#[test]
+fn trip_test() {
+    // channel 1 is not monitored
+    let mut detector = TripDetector::new(vec![Some(1000), None], TripAction::Off, 3, 10);
+
+    // a single spike and a read error do not trip
+    assert!(detector.check(&[Some(1500), Some(5000)]).is_empty());
+    assert!(detector.check(&[Some(500), Some(5000)]).is_empty());
+    assert!(detector.check(&[Some(1500), None]).is_empty());
+    assert!(detector.check(&[None, None]).is_empty());
+    assert!(detector.check(&[Some(-1500), None]).is_empty());
+    let events = detector.check(&[Some(1500), None]);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, TripKind::Tripped);
+    assert_eq!(events[0].index, 0);
+    assert_eq!(events[0].action, TripAction::Off);
+    assert_eq!(detector.tripped(), vec![true, false]);
+
+    // no repeated trip, and released only 10 % below the threshold
+    assert!(detector.check(&[Some(1500), None]).is_empty());
+    assert!(detector.check(&[Some(910), None]).is_empty());
+    let events = detector.check(&[Some(890), None]);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, TripKind::Released);
+    assert!(!detector.is_tripped(0));
+    assert_eq!(detector.history().len(), 2);
+
+    // a rescan keeps the tripped channel, now at index 1, and drops the removed one
+    let id = |dev, ch| ChannelId::new(DEFAULT_CONTROLLER, 0, dev, ch);
+    let mut previous = TripDetector::new(vec![Some(1000), Some(1000)], TripAction::Off, 1, 10);
+    previous.check(&[Some(1500), Some(1500)]);
+    let mut detector = TripDetector::new(vec![Some(1000); 2], TripAction::Off, 1, 10);
+    detector.keep_state(&previous, &[id(0, 0), id(0, 1)], &[id(1, 0), id(0, 0)]);
+    assert_eq!(detector.tripped(), vec![false, true]);
+    let history = detector.history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].index, 1);
+    // not tripped again while it stays above the threshold
+    assert_eq!(detector.check(&[None, Some(1500)]).len(), 0);
+}
+
+#[test]
+fn ramp_test() {
+    let plan = RampPlan {
+        step: 10,
+        waiting_time: 500,
+        switch_off: false,
+    };
+    let channels = vec![
+        ChannelProgress {
+            current: 0,
+            target: 95,
+        },
+        ChannelProgress {
+            current: 100,
+            target: 70,
+        },
+    ];
+
+    // the slowest channel takes 10 steps
+    assert_eq!(ramping::eta(&channels, plan), 5000);
+    assert_eq!(ramping::next_step(0, 95, 10), 10);
+    assert_eq!(ramping::next_step(90, 95, 10), 95);
+    assert_eq!(ramping::next_step(100, 70, 10), 90);
+    assert_eq!(ramping::next_step(70, 70, 10), 70);
+
+    // only one ramp at a time
+    let mut status = RampStatus::default();
+    assert!(!status.pause() && !status.resume() && !status.abort());
+    assert!(status.begin(channels.clone(), plan));
+    assert_eq!(status.state, RampState::Ramping);
+    assert_eq!(status.eta_ms, 5000);
+    assert!(!status.begin(channels.clone(), plan));
+    assert!(status.pause());
+    assert!(!status.pause());
+    assert!(!status.begin(channels.clone(), plan));
+    assert!(status.resume());
+    assert!(status.abort());
+    assert_eq!(status.state, RampState::Aborted);
+    assert!(!status.abort());
+
+    // the emergency ramp-down aborts the ramp and can not be paused
+    assert!(status.begin(channels.clone(), plan));
+    assert!(status.request_emergency());
+    assert!(!status.request_emergency());
+    assert_eq!(status.state, RampState::Aborted);
+    let emergency = RampPlan {
+        switch_off: true,
+        ..plan
+    };
+    assert!(status.begin(channels, emergency));
+    assert!(!status.pause() && !status.abort());
+    status.finish(RampState::Idle, emergency);
+    assert_eq!(status.state, RampState::Idle);
+    assert_eq!(status.eta_ms, 0);
+    assert!(!status.is_emergency);
+}
+
+#[test]
+fn ramp_run_test() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let plan = RampPlan {
+        step: 20,
+        waiting_time: 1,
+        switch_off: true,
+    };
+    // channels 2 and 3 are on the disconnected controller
+    let ramp = |plan: RampPlan| {
+        let status = Mutex::new(RampStatus::default());
+        let channels = vec![
+            ChannelProgress {
+                current: 50,
+                target: 0
+            };
+            4
+        ];
+        assert!(status.lock().unwrap().begin(channels, plan));
+        let writes = Mutex::new(Vec::new());
+        let state = runtime.block_on(ramping::run(
+            &status,
+            &Notify::new(),
+            plan,
+            |index, write| {
+                writes.lock().unwrap().push((index, write));
+                let result = if index >= 2 {
+                    Err("Serial link is lost")
+                } else {
+                    Ok(())
+                };
+                async move { result }
+            },
+        ));
+        (
+            state,
+            status.into_inner().unwrap(),
+            writes.into_inner().unwrap(),
+        )
+    };
+
+    // the emergency ramp-down goes on with the other controller and switches off everything
+    let (state, status, writes) = ramp(plan);
+    assert_eq!(state, RampState::Failed);
+    assert_eq!(status.channels[0].current, 0);
+    assert_eq!(status.channels[1].current, 0);
+    assert_eq!(status.channels[2].current, 50);
+    let error = status.error.unwrap();
+    assert!(error.contains("channel 2") && error.contains("channel 3"));
+    for index in 0..4 {
+        assert!(writes.contains(&(index, RampWrite::Off)));
+    }
+    let steps = |index| {
+        writes
+            .iter()
+            .filter(|&&(i, write)| i == index && write != RampWrite::Off)
+            .count()
+    };
+    assert_eq!(steps(0), 3);
+    assert_eq!(steps(2), 1);
+
+    // a normal ramp stops at the first error
+    let (state, status, writes) = ramp(RampPlan {
+        switch_off: false,
+        ..plan
+    });
+    assert_eq!(state, RampState::Failed);
+    assert!(status.error.unwrap().contains("channel 2"));
+    assert_eq!(writes.len(), 3);
+    assert!(!writes.iter().any(|&(_, write)| write == RampWrite::Off));
+
+    // an abort stops the long wait between the steps at once
+    let plan = RampPlan {
+        step: 10,
+        waiting_time: 60_000,
+        switch_off: false,
+    };
+    let status = Mutex::new(RampStatus::default());
+    let channels = vec![ChannelProgress {
+        current: 0,
+        target: 100,
+    }];
+    assert!(status.lock().unwrap().begin(channels, plan));
+    let changed = Notify::new();
+    let run = ramping::run(&status, &changed, plan, |_, _| async { Ok::<(), &str>(()) });
+    let abort = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(status.lock().unwrap().abort());
+        changed.notify_one();
+    };
+    let (state, ()) = runtime
+        .block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(run, abort) }).await
+        })
+        .expect("the ramp is not stopped by the abort");
+    assert_eq!(state, RampState::Aborted);
+    assert_eq!(status.lock().unwrap().channels[0].current, 10);
+}
+
+#[test]
+fn limits_test() {
+    // channel 1 is a thin detector
+    let limits = VoltageLimits::new(3000, vec![None, Some(500), Some(4000)], 1, 500);
+    assert_eq!(limits.max_voltage(0), 3000);
+    assert_eq!(limits.max_voltage(1), 500);
+    assert_eq!(limits.max_voltage(2), 3000);
+
+    assert!(limits.check_setpoints(&[3000, 500, 0]).is_ok());
+    assert_eq!(
+        limits.check_setpoints(&[3000, 5000, 0]),
+        Err(LimitError::Voltage {
+            index: 1,
+            target: 5000,
+            max: 500
+        })
+    );
+    assert!(limits.check_setpoints(&[-10, 0, 0]).is_err());
+    assert!(matches!(
+        limits.check_setpoints(&[0, 0]),
+        Err(LimitError::ChannelNumber { .. })
+    ));
+
+    // 5 every 500 ms is 1 V/s
+    assert!(limits.check_slew(5, 500).is_ok());
+    assert!(matches!(
+        limits.check_slew(500, 100),
+        Err(LimitError::Slew { slew: 5000, .. })
+    ));
+    assert!(limits.check_slew(1, 60_000).is_err());
+    assert!(limits.check_slew(0, 500).is_err());
+}
+
+#[test]
+fn audit_test() {
+    let dir = std::env::temp_dir().join(format!("mhv4_audit_test_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("Cannot create the directory");
+    let path = dir.join("audit.jsonl");
+    assert_eq!(
+        audit::read(&path, &AuditFilter::default(), 10).ok(),
+        Some(Vec::new())
+    );
+
+    let entry = |time_ms: u64, user: &str, action: &str| AuditEntry {
+        time_ms,
+        user: user.to_string(),
+        address: Some(String::from("127.0.0.1:50000")),
+        action: action.to_string(),
+        payload: serde_json::json!([1000, 0]),
+        previous: serde_json::json!({"setpoints": [0, 0]}),
+        outcome: String::from("true"),
+    };
+    {
+        let mut audit_log = AuditLog::open(&path).expect("Cannot open the audit log");
+        audit_log.append(&entry(1000, "alice", "apply")).unwrap();
+        audit_log.append(&entry(2000, "server", "se")).unwrap();
+    }
+    // appended to the existing file, and a line cut by a crash is skipped
+    {
+        let audit_log = AuditLog::open(&path).expect("Cannot open the audit log");
+        let writer = AuditWriter::start(audit_log).expect("Cannot start the audit writer");
+        writer.send(entry(3000, "bob", "onoff"));
+        writer.close();
+    }
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|mut x| x.write_all(b"{\"time_ms\": 4000, \"us"))
+        .unwrap();
+
+    let all = audit::read(&path, &AuditFilter::default(), 10).unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0], entry(1000, "alice", "apply"));
+    // the latest ones
+    let last = audit::read(&path, &AuditFilter::default(), 2).unwrap();
+    assert_eq!(
+        last.iter().map(|x| x.time_ms).collect::<Vec<_>>(),
+        vec![2000, 3000]
+    );
+
+    let filter = AuditFilter {
+        user: Some(String::from("bob")),
+        ..Default::default()
+    };
+    assert_eq!(
+        audit::read(&path, &filter, 10).unwrap(),
+        vec![entry(3000, "bob", "onoff")]
+    );
+    let filter = AuditFilter {
+        from_ms: Some(1500),
+        to_ms: Some(2500),
+        ..Default::default()
+    };
+    assert_eq!(audit::read(&path, &filter, 10).unwrap().len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_test() {
+    let config = Config::load("channels.example.json").expect("Cannot load the example");
+    let id = |bus, dev, ch| ChannelId::new(DEFAULT_CONTROLLER, bus, dev, ch);
+    assert_eq!(
+        config.find(&id(0, 0, 1)).map(|x| x.name.as_str()),
+        Some("SSD2")
+    );
+    assert_eq!(
+        config.find_by_name("PPAC").and_then(|x| x.max_voltage),
+        Some(3000)
+    );
+    assert!(config.find(&id(0, 1, 1)).is_none());
+
+    // the modules of the default simulator
+    let mut scanned = (0..8)
+        .map(|i| ScannedChannel {
+            id: id(0, i / 4, i % 4),
+            is_positive: true,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(config.check(&scanned), Ok(()));
+    scanned[1].is_positive = false;
+    assert!(matches!(
+        config.check(&scanned),
+        Err(ConfigError::Mismatch(_))
+    ));
+    assert!(matches!(
+        config.check(&scanned[2..]),
+        Err(ConfigError::Mismatch(_))
+    ));
+    // a controller is checked on its own, without the detectors of the others
+    assert!(matches!(
+        config.check_controller(DEFAULT_CONTROLLER, &scanned[2..]),
+        Err(ConfigError::Mismatch(_))
+    ));
+    assert_eq!(config.check_controller("focal", &[]), Ok(()));
+
+    // typos and duplicated detectors are rejected
+    let channel = r#"{"bus": 0, "dev": 0, "ch": 0, "name": "SSD1"}"#;
+    assert!(Config::parse(&format!(r#"{{"channels": [{}]}}"#, channel)).is_ok());
+    assert!(matches!(
+        Config::parse(&format!(r#"{{"channels": [{0}, {0}]}}"#, channel)),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        Config::parse(
+            r#"{"channels": [{"bus": 0, "dev": 0, "ch": 0, "name": "a", "max_volt": 1}]}"#
+        ),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        Config::parse(r#"{"channels": [{"bus": 0, "dev": 0, "ch": 4, "name": "a"}]}"#),
+        Err(ConfigError::Invalid(_))
+    ));
+
+    // the same address of another controller is another detector
+    let other = r#"{"controller": "focal", "bus": 0, "dev": 0, "ch": 0, "name": "SSD2"}"#;
+    let config = Config::parse(&format!(r#"{{"channels": [{}, {}]}}"#, channel, other))
+        .expect("Cannot parse the controllers");
+    assert_eq!(
+        config
+            .find(&ChannelId::new("focal", 0, 0, 0))
+            .map(|x| x.name.as_str()),
+        Some("SSD2")
+    );
+    assert_eq!(config.channels[0].controller, DEFAULT_CONTROLLER);
+}
+
+#[test]
+fn channel_id_test() {
+    let id = ChannelId::new("target", 0, 1, 2);
+    assert_eq!(id.to_string(), "target:0:1:2");
+    assert_eq!("target:0:1:2".parse(), Ok(id));
+    assert_eq!(
+        "0:1:2".parse(),
+        Ok(ChannelId::new(DEFAULT_CONTROLLER, 0, 1, 2))
+    );
+    for text in [
+        "",
+        "0:1",
+        ":0:1:2",
+        "target:0:1",
+        "target:0:1:x",
+        "a:b:0:1:2",
+    ] {
+        assert!(text.parse::<ChannelId>().is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn credentials_test() {
+    let credentials = Credentials::load("users.example.json").expect("Cannot load the example");
+    let user = credentials
+        .authenticate(None, "change-me-operator-token")
+        .expect("Cannot find the operator");
+    assert_eq!(user.name, "hv-expert");
+    assert_eq!(user.role, Role::Operator);
+    assert!(Role::Observer < Role::Operator);
+
+    // the name of the basic auth should match
+    assert!(credentials
+        .authenticate(Some("shifter"), "change-me-observer-token")
+        .is_some());
+    assert!(credentials
+        .authenticate(Some("hv-expert"), "change-me-observer-token")
+        .is_none());
+    assert!(credentials.authenticate(None, "change-me").is_none());
+    assert!(credentials.authenticate(None, "").is_none());
+
+    // short and duplicated tokens are rejected
+    let user = r#"{"name": "a", "role": "observer", "token": "0123456789abcdef"}"#;
+    assert!(Credentials::parse(&format!(r#"{{"users": [{}]}}"#, user)).is_ok());
+    assert!(matches!(
+        Credentials::parse(&format!(r#"{{"users": [{0}, {0}]}}"#, user)),
+        Err(CredentialsError::Invalid(_))
+    ));
+    assert!(matches!(
+        Credentials::parse(r#"{"users": [{"name": "a", "role": "observer", "token": "short"}]}"#),
+        Err(CredentialsError::Invalid(_))
+    ));
+    assert!(matches!(
+        Credentials::parse(
+            r#"{"users": [{"name": "a", "role": "admin", "token": "0123456789abcdef"}]}"#
+        ),
+        Err(CredentialsError::Parse(_))
+    ));
+}
+
+#[test]
+fn recorder_test() {
+    let dir = std::env::temp_dir().join(format!("mhv4_recorder_test_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut recorder = Recorder::new(RecorderConfig {
+        dir: dir.clone(),
+        rotate_ms: 60_000,
+        retention_ms: 3_600_000,
+        downsample_after_ms: 60_000,
+        bucket_ms: 1000,
+    })
+    .expect("Cannot create the recorder");
+
+    // 2 channels every 100 ms for 2 minutes, so 2 files
+    let channels = vec![
+        ChannelId::new("target", 0, 0, 0),
+        ChannelId::new("focal", 0, 1, 3),
+    ];
+    let start = 1_800_000_000_000;
+    for i in 0..1200 {
+        let record = Record {
+            time_ms: start + i * 100,
+            voltages: vec![Some(i as isize), Some(1000)],
+            currents: vec![Some(10), if i == 5 { None } else { Some(i as isize % 7) }],
+        };
+        recorder.record(&channels, &record).expect("Cannot record");
+    }
+    let files = recorder::record_files(&dir).expect("Cannot list the records");
+    assert_eq!(files.len(), 2);
+    let (read_channels, records) = recorder::read_file(&files[0]).expect("Cannot read");
+    assert_eq!(read_channels, channels);
+    assert_eq!(records.len(), 600);
+    assert_eq!(records[5].currents, vec![Some(10), None]);
+
+    // min and max of every second
+    let reduced = recorder::downsample(&records, 1000);
+    assert_eq!(reduced.len(), 120);
+    assert_eq!(reduced[0].time_ms, start);
+    assert_eq!(reduced[0].voltages, vec![Some(0), Some(1000)]);
+    assert_eq!(reduced[1].voltages, vec![Some(9), Some(1000)]);
+    assert_eq!(reduced[1].currents, vec![Some(10), Some(6)]);
+
+    // the first file is old enough to be downsampled, the second is expired
+    let now = std::time::SystemTime::now();
+    let age = |secs| now - Duration::from_secs(secs);
+    let set_age = |path: &std::path::Path, secs| {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|x| x.set_modified(age(secs)))
+            .expect("Cannot set the time");
+    };
+    set_age(&files[0], 120);
+    set_age(&files[1], 7200);
+    let mut recorder = Recorder::new(RecorderConfig {
+        dir: dir.clone(),
+        rotate_ms: 60_000,
+        retention_ms: 3_600_000,
+        downsample_after_ms: 60_000,
+        bucket_ms: 1000,
+    })
+    .expect("Cannot create the recorder");
+    recorder
+        .maintain(mhv4_monitor::now_ms())
+        .expect("Cannot maintain");
+    let files = recorder::record_files(&dir).expect("Cannot list the records");
+    assert_eq!(files.len(), 1);
+    assert!(recorder::is_downsampled(&files[0]));
+    let (_, records) = recorder::read_file(&files[0]).expect("Cannot read");
+    assert_eq!(records, reduced);
+
+    // the files before the controllers are of the default one
+    let path = dir.join("mhv4_2026-10-18_130512.csv");
+    std::fs::write(&path, "time_ms,voltage 0:1:2,current 0:1:2\n1000,50,3\n").unwrap();
+    let (read_channels, records) = recorder::read_file(&path).expect("Cannot read");
+    assert_eq!(
+        read_channels,
+        vec![ChannelId::new(DEFAULT_CONTROLLER, 0, 1, 2)]
+    );
+    assert_eq!(records[0].voltages, vec![Some(50)]);
+
+    // a rescan and a restart in the same second, every file has one header
+    let config = RecorderConfig {
+        dir: dir.join("same_second"),
+        rotate_ms: 60_000,
+        retention_ms: 3_600_000,
+        downsample_after_ms: 60_000,
+        bucket_ms: 1000,
+    };
+    let record = |time_ms, num| Record {
+        time_ms: start + time_ms,
+        voltages: vec![Some(100); num],
+        currents: vec![Some(1); num],
+    };
+    let mut recorder = Recorder::new(config.clone()).expect("Cannot create the recorder");
+    recorder.record(&channels, &record(0, 2)).unwrap();
+    recorder.record(&channels, &record(100, 2)).unwrap();
+    recorder.record(&channels[..1], &record(200, 1)).unwrap();
+    let mut recorder = Recorder::new(config.clone()).expect("Cannot create the recorder");
+    recorder.record(&channels, &record(300, 2)).unwrap();
+    let files = recorder::record_files(&config.dir).expect("Cannot list the records");
+    assert_eq!(files.len(), 3);
+    let read = |path| recorder::read_file(path).expect("Cannot read");
+    assert_eq!(
+        read(&files[0]),
+        (channels.clone(), vec![record(0, 2), record(100, 2)])
+    );
+    assert_eq!(
+        read(&files[1]),
+        (channels[..1].to_vec(), vec![record(200, 1)])
+    );
+    assert_eq!(read(&files[2]), (channels.clone(), vec![record(300, 2)]));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn history_test() {
+    // a spike at 500 of a slow ramp
+    let points = (0..1000u64)
+        .map(|i| (i * 100, if i == 500 { 5000 } else { i as isize }))
+        .collect::<Vec<_>>();
+    for reduced in [history::min_max(&points, 100), history::lttb(&points, 100)] {
+        assert!(reduced.len() <= 100);
+        assert!(reduced.contains(&(50_000, 5000)));
+        assert!(reduced.windows(2).all(|x| x[0].0 < x[1].0));
+    }
+    assert_eq!(history::lttb(&points, 100)[0], points[0]);
+    assert_eq!(history::lttb(&points, 100)[99], points[999]);
+    assert_eq!(history::min_max(&points[..10], 100), points[..10].to_vec());
+
+    let dir = std::env::temp_dir().join(format!("mhv4_history_test_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut recorder = Recorder::new(RecorderConfig {
+        dir: dir.clone(),
+        rotate_ms: 60_000,
+        retention_ms: u64::MAX,
+        downsample_after_ms: u64::MAX,
+        bucket_ms: 1000,
+    })
+    .expect("Cannot create the recorder");
+    let start = mhv4_monitor::now_ms() - 100_000;
+    for i in 0..1000 {
+        let record = Record {
+            time_ms: start + i * 100,
+            voltages: vec![Some(i as isize), Some(0)],
+            currents: vec![Some(1), None],
+        };
+        recorder
+            .record(
+                &[
+                    ChannelId::new("target", 0, 0, 0),
+                    ChannelId::new("focal", 1, 2, 3),
+                ],
+                &record,
+            )
+            .expect("Cannot record");
+    }
+    assert_eq!(
+        recorder::file_start_ms(&recorder::record_files(&dir).unwrap()[0]),
+        Some(start / 1000 * 1000)
+    );
+
+    let result = history::query(
+        &dir,
+        &[ChannelId::new("focal", 1, 2, 3)],
+        start + 10_000,
+        start + 20_000,
+        10,
+        Downsampling::MinMax,
+    )
+    .expect("Cannot query");
+    assert_eq!(result.len(), 1);
+    assert_eq!(
+        (
+            result[0].controller.as_str(),
+            result[0].bus,
+            result[0].dev,
+            result[0].ch
+        ),
+        ("focal", 1, 2, 3)
+    );
+    assert_eq!(result[0].voltage.value.len(), 10);
+    assert!(result[0].current.value.is_empty());
+
+    let result = history::query(&dir, &[], start, start + 100_000, 1000, Downsampling::Lttb)
+        .expect("Cannot query");
+    assert_eq!(result.len(), 2);
+    // in the order of the ids, "focal" first
+    assert_eq!(result[1].voltage.time_ms.len(), 1000);
+    assert_eq!(result[1].voltage.value[999], 999);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn client_test() {
+    assert_eq!(client::encode("alice"), "alice");
+    assert_eq!(client::encode("a b&c=d"), "a%20b%26c%3Dd");
+    assert_eq!(client::encode("山田"), "%E5%B1%B1%E7%94%B0");
+
+    // an IPv6 address is kept in the brackets
+    assert_eq!(client::address("[::1]"), "[::1]:80");
+    assert_eq!(client::address("[::1]:8080"), "[::1]:8080");
+    assert_eq!(client::address("::1"), "[::1]:80");
+    assert_eq!(client::address("localhost"), "localhost:80");
+    assert_eq!(client::address("localhost:8080"), "localhost:8080");
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot bind 127.0.0.1");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 1024];
+        let size = stream.read(&mut request).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]")
+            .unwrap();
+        String::from_utf8_lossy(&request[..size]).to_string()
+    });
+    let (status, body) = client::request(
+        &format!("http://127.0.0.1:{}/", port),
+        "GET",
+        "/audit?user=a%20b",
+        None,
+        Some("token"),
+    )
+    .expect("Cannot send the request");
+    assert_eq!((status, body.as_str()), (200, "[]"));
+    let request = server.join().unwrap();
+    assert!(request.starts_with("GET /audit?user=a%20b HTTP/1.1\r\n"));
+    assert!(request.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+    assert!(request.contains("Authorization: Bearer token\r\n"));
+}
+
+// the mock MRC-1 which keeps the written commands, the first one is held until "release",
+// a silent one never replies like a power-cycled MRC-1
+struct HeldTransport {
+    port: Box<dyn Transport>,
+    commands: std::sync::Arc<Mutex<Vec<String>>>,
+    hold: Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>,
+    silent: bool,
+}
+
+impl Transport for HeldTransport {
+    fn description(&self) -> String {
+        self.port.description()
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        self.port.clear_input()
+    }
+}
+
+impl Read for HeldTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.silent {
+            std::thread::sleep(Duration::from_millis(5));
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.port.read(buf)
+    }
+}
+
+impl Write for HeldTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some((started, release)) = self.hold.take() {
+            started.send(()).unwrap();
+            release.recv().unwrap();
+        }
+        let command = String::from_utf8_lossy(buf).trim_end().to_string();
+        self.commands.lock().unwrap().push(command);
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+fn mock_port() -> Box<dyn Transport> {
+    transport::open("mock://0:0:27", 9600, Duration::from_millis(100)).expect("Cannot open")
+}
+
+fn reconnect(
+    open: impl FnMut() -> Result<Box<dyn Transport>, TransportError> + Send + 'static,
+    max_timeouts: u32,
+) -> Reconnect {
+    Reconnect {
+        open: Box::new(open),
+        min_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(20),
+        max_timeouts,
+    }
+}
+
+#[test]
+fn link_test() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let read = |reg| Command::Read {
+        bus: 0,
+        dev: 0,
+        reg,
+    };
+
+    // the emergency commands go first, then the control, then the monitor
+    let commands = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let (started_tx, started) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel();
+    let port = HeldTransport {
+        port: mock_port(),
+        commands: commands.clone(),
+        hold: Some((started_tx, release_rx)),
+        silent: false,
+    };
+    let handle = SerialHandle::spawn(
+        "target",
+        Ok(Box::new(port)),
+        reconnect(|| Ok(mock_port()), 5),
+        Duration::from_millis(500),
+        1024,
+    );
+    runtime.block_on(async {
+        let mut first = Box::pin(handle.request(read(32), Priority::Monitor));
+        assert!(futures::poll!(&mut first).is_pending());
+        started.recv().unwrap();
+        let mut monitor = Box::pin(handle.request(read(33), Priority::Monitor));
+        let mut control = Box::pin(handle.request(read(34), Priority::Control));
+        let mut emergency = Box::pin(handle.request(read(35), Priority::Emergency));
+        for request in [&mut monitor, &mut control, &mut emergency] {
+            assert!(futures::poll!(request).is_pending());
+        }
+        release.send(()).unwrap();
+        let results = futures::join!(first, monitor, control, emergency);
+        assert!(results.0.is_ok() && results.1.is_ok() && results.2.is_ok() && results.3.is_ok());
+    });
+    assert_eq!(
+        *commands.lock().unwrap(),
+        vec!["re 0 0 32", "re 0 0 35", "re 0 0 34", "re 0 0 33"]
+    );
+    assert_eq!(handle.stats().commands, 4);
+
+    // a lost port fails the commands at once until it is opened again
+    let mut attempts = 0;
+    let handle = SerialHandle::spawn(
+        "focal",
+        Err(TransportError::Io(std::io::ErrorKind::NotFound.into())),
+        reconnect(
+            move || {
+                attempts += 1;
+                match attempts {
+                    1 => Err(TransportError::Io(std::io::ErrorKind::NotFound.into())),
+                    _ => Ok(mock_port()),
+                }
+            },
+            5,
+        ),
+        Duration::from_millis(500),
+        1024,
+    );
+    runtime.block_on(async {
+        assert!(matches!(
+            handle.request(read(32), Priority::Control).await,
+            Err(LinkError::Disconnected)
+        ));
+        let mut link = handle.subscribe_link();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *link.borrow_and_update() != LinkState::Connected {
+                link.changed().await.unwrap();
+            }
+        })
+        .await
+        .expect("the port is not opened again");
+        assert!(handle.request(read(32), Priority::Control).await.is_ok());
+    });
+    assert_eq!(handle.stats().reconnects, 1);
+
+    // the link is lost after "max_timeouts" replies in a row did not arrive
+    let silent = HeldTransport {
+        port: mock_port(),
+        commands: std::sync::Arc::new(Mutex::new(Vec::new())),
+        hold: None,
+        silent: true,
+    };
+    let handle = SerialHandle::spawn(
+        "silent",
+        Ok(Box::new(silent)),
+        reconnect(|| Ok(mock_port()), 2),
+        Duration::from_millis(50),
+        1024,
+    );
+    runtime.block_on(async {
+        let mut link = handle.subscribe_link();
+        for _ in 0..2 {
+            assert!(matches!(
+                handle.request(read(32), Priority::Monitor).await,
+                Err(LinkError::Transport(TransportError::Timeout(_)))
+            ));
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            link.changed().await.unwrap();
+            while *link.borrow_and_update() != LinkState::Connected {
+                link.changed().await.unwrap();
+            }
+        })
+        .await
+        .expect("the port is not opened again");
+        assert!(handle.request(read(32), Priority::Monitor).await.is_ok());
+    });
+    assert_eq!(handle.stats().timeouts, 2);
+    assert_eq!(handle.stats().reconnects, 1);
+}